@@ -0,0 +1,47 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use exotherm::{
+    database::{record::RecordStruct, view::ArchivedRecord},
+    schema,
+};
+
+schema!(Person ref PersonRef {
+    0 -> name: [name_index] String,
+    1 -> password:[] String,
+    2 -> bio: [] String,
+    3 -> age: [] u32,
+});
+
+fn decode(c: &mut Criterion) {
+    let person = Person {
+        name: String::from("NameNameNameNameNamevName"),
+        password: String::from("TestTestTestTestTest"),
+        bio: "lorem ipsum ".repeat(64),
+        age: 42,
+    };
+    let bytes = person.serialize().unwrap().to_vec();
+
+    c.bench_function("decode owned", |b| {
+        b.iter(|| Person::decode(black_box(&bytes)).unwrap())
+    });
+    c.bench_function("decode owned single column", |b| {
+        b.iter(|| Person::decode(black_box(&bytes)).unwrap().age)
+    });
+    let record = ArchivedRecord::new(&bytes);
+    c.bench_function("view", |b| {
+        b.iter(|| black_box(&record).view::<PersonRef>().unwrap().name.len())
+    });
+    c.bench_function("view single column", |b| {
+        b.iter(|| black_box(&record).view::<PersonRef>().unwrap().age)
+    });
+    c.bench_function("copy and view", |b| {
+        b.iter(|| {
+            ArchivedRecord::new(black_box(&bytes))
+                .view::<PersonRef>()
+                .unwrap()
+                .age
+        })
+    });
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
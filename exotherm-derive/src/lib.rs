@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    Data, DeriveInput, Fields, Ident, LitInt, LitStr, Type, parse::ParseStream, parse_macro_input,
+};
+
+///Derive `RecordStruct` for a struct, as an alternative to the `schema!` macro
+///
+/// Every field needs an `#[exo(id = N)]` attribute with a unique column number.
+/// Adding `index = "name"` generates an index key function with that name.
+/// The table name defaults to the struct name, `#[exo(table = "...", namespace = "...")]`
+/// on the struct sets a stable name instead, names must not be empty or contain `.` or NUL. `#[exo(view = "PersonRef")]` also generates a
+/// borrowed view of the archived row. Every column also gets a `project_<field>()` projection
+/// and a `set_<field>()` setter on the generated `<Name>Patch` used for partial updates.
+/// Fields of type `Blob` or `Option<Blob>` keep their data in the blobstore.
+/// ```ignore
+/// #[derive(Debug, Clone, Record)]
+/// #[exo(namespace = "auth", table = "people")]
+/// pub struct Person {
+///     #[exo(id = 0, index = "name_index")]
+///     pub name: String,
+///     #[exo(id = 1)]
+///     pub password: String,
+/// }
+/// ```
+#[proc_macro_derive(Record, attributes(exo))]
+pub fn derive_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+///Accepts both `name` and `"name"`, as schema! can only forward identifiers
+fn parse_ident(input: ParseStream) -> syn::Result<Ident> {
+    if input.peek(LitStr) {
+        input.parse::<LitStr>()?.parse()
+    } else {
+        input.parse()
+    }
+}
+
+struct Column {
+    field: Ident,
+    ty: Type,
+    id: u16,
+    index: Option<Ident>,
+}
+
+fn parse_column(field: &syn::Field) -> syn::Result<Column> {
+    let ident = field.ident.clone().expect("named field");
+    let mut id: Option<u16> = None;
+    let mut index: Option<Ident> = None;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("exo")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                let lit: LitInt = meta.value()?.parse()?;
+                id = Some(lit.base10_parse()?);
+                Ok(())
+            } else if meta.path.is_ident("index") {
+                index = Some(parse_ident(meta.value()?)?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported exo attribute, expected `id` or `index`"))
+            }
+        })?;
+    }
+    let Some(id) = id else {
+        return Err(syn::Error::new_spanned(
+            &ident,
+            "missing column number, add #[exo(id = N)]",
+        ));
+    };
+    Ok(Column {
+        field: ident,
+        ty: field.ty.clone(),
+        id,
+        index,
+    })
+}
+
+///Table and namespace names are joined with `.` and the empty table holds the data of the tenant
+fn parse_name(input: ParseStream) -> syn::Result<String> {
+    let lit: LitStr = input.parse()?;
+    let name = lit.value();
+    if name.is_empty() || name.contains(['\0', '.']) {
+        return Err(syn::Error::new_spanned(
+            lit,
+            "names must not be empty or contain `.` or NUL",
+        ));
+    }
+    Ok(name)
+}
+
+struct Table {
+    name: String,
+    view: Option<Ident>,
+}
+
+fn parse_table(input: &DeriveInput) -> syn::Result<Table> {
+    let mut table: Option<String> = None;
+    let mut namespace: Option<String> = None;
+    let mut view: Option<Ident> = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("exo")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = Some(parse_name(meta.value()?)?);
+                Ok(())
+            } else if meta.path.is_ident("namespace") {
+                namespace = Some(parse_name(meta.value()?)?);
+                Ok(())
+            } else if meta.path.is_ident("view") {
+                view = Some(parse_ident(meta.value()?)?);
+                Ok(())
+            } else {
+                Err(meta
+                    .error("unsupported exo attribute, expected `table`, `namespace` or `view`"))
+            }
+        })?;
+    }
+    let table = table.unwrap_or_else(|| input.ident.to_string());
+    let name = match namespace {
+        Some(namespace) => format!("{namespace}.{table}"),
+        None => table,
+    };
+    Ok(Table { name, view })
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "Record can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "Record can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut columns = Vec::<Column>::new();
+    let mut seen = HashMap::<u16, Ident>::new();
+    for field in &fields.named {
+        let column = parse_column(field)?;
+        if let Some(previous) = seen.get(&column.id) {
+            return Err(syn::Error::new_spanned(
+                &column.field,
+                format!(
+                    "duplicate column number {} (already used by `{}`)",
+                    column.id, previous
+                ),
+            ));
+        }
+        seen.insert(column.id, column.field.clone());
+        columns.push(column);
+    }
+
+    let Table { name: table, view } = parse_table(&input)?;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let index_fns = columns.iter().filter_map(|c| {
+        let index = c.index.as_ref()?;
+        let (ty, id) = (&c.ty, c.id);
+        Some(quote! {
+            /// Generates an index key for a column
+            /// Function generated by exotherm
+            pub fn #index(row: ::exotherm::uuid::Uuid, value: &#ty) -> ::exotherm::database::key::Key {
+                use ::exotherm::database::record::RecordStruct;
+                use ::exotherm::database::values_indices::*;
+                ::exotherm::database::key::Key::new_index(
+                    ::exotherm::database::key::Tenant::Unset,
+                    Self::name(),
+                    #id,
+                    value.index(),
+                    row,
+                )
+            }
+        })
+    });
+    let projections = columns.iter().map(|c| {
+        let (ty, id) = (&c.ty, c.id);
+        let project = format_ident!("project_{}", c.field);
+        quote! {
+            /// Typed handle to a single column, for reading it without decoding the whole row
+            /// Function generated by exotherm
+            pub const fn #project() -> ::exotherm::database::view::Projection<Self, #ty> {
+                ::exotherm::database::view::Projection::new(#id)
+            }
+        }
+    });
+    let index_calls = columns.iter().filter_map(|c| {
+        let index = c.index.as_ref()?;
+        let field = &c.field;
+        Some(quote! { Self::#index(row, &self.#field), })
+    });
+    let corpus = columns.iter().map(|c| {
+        let (field, id) = (&c.field, c.id as usize);
+        quote! { (#id, self.#field.encode_db()) }
+    });
+    let blob_columns = columns.iter().map(|c| {
+        let ty = &c.ty;
+        quote! { || <#ty as ::exotherm::database::values_indices::DbValueEncode>::BLOB }
+    });
+    let blobs = columns.iter().map(|c| {
+        let field = &c.field;
+        quote! { blobs.extend(self.#field.blob()); }
+    });
+    let decode = columns.iter().map(|c| {
+        let (field, id) = (&c.field, c.id as usize);
+        //Rows written before a column was added are shorter, `Option` columns read them as `None`
+        quote! {
+            #field: match from.get_mut(#id) {
+                Some(value) => std::mem::replace(value, DbValue::None).try_into()?,
+                None => DbValue::None
+                    .try_into()
+                    .map_err(|_| ::exotherm::error::ConvertError::MissingColumn)?,
+            }
+        }
+    });
+    let patch = if input.generics.params.is_empty() {
+        let patch = format_ident!("{}Patch", name);
+        let setters = columns.iter().map(|c| {
+            let (field, ty, id) = (&c.field, &c.ty, c.id);
+            let set = format_ident!("set_{}", field);
+            quote! {
+                pub fn #set(&mut self, value: #ty) -> &mut Self {
+                    self.record.#field = value;
+                    if !self.changed.contains(&#id) {
+                        self.changed.push(#id);
+                    }
+                    self
+                }
+            }
+        });
+        quote! {
+            ///Typed setters for a partial update, generated by exotherm
+            pub struct #patch<'a> {
+                record: &'a mut #name,
+                changed: Vec<u16>,
+            }
+            impl #patch<'_> {
+                #(#setters)*
+            }
+            impl ::exotherm::database::record::Patchable for #name {
+                type Patch<'a> = #patch<'a>;
+                fn patch(&mut self) -> Self::Patch<'_> {
+                    #patch {
+                        record: self,
+                        changed: Vec::new(),
+                    }
+                }
+                fn changed(patch: &Self::Patch<'_>) -> Vec<u16> {
+                    patch.changed.clone()
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let view = match view {
+        Some(view) if input.generics.params.is_empty() => {
+            let fields = columns.iter().map(|c| {
+                let (field, ty) = (&c.field, &c.ty);
+                quote! { pub #field: <#ty as ::exotherm::database::view::ColumnView>::View<'a> }
+            });
+            let from_archived = columns.iter().map(|c| {
+                let (field, ty, id) = (&c.field, &c.ty, c.id as usize);
+                quote! { #field: <#ty as ColumnView>::view(row.0.get(#id))? }
+            });
+            quote! {
+                ///Borrowed view of an archived row, generated by exotherm
+                #[derive(Debug)]
+                pub struct #view<'a> {
+                    #(#fields),*
+                }
+                impl<'a> ::exotherm::database::view::RecordView<'a> for #view<'a> {
+                    fn from_archived(
+                        row: &'a ::exotherm::database::values_indices::ArchivedRow,
+                    ) -> Result<Self, ::exotherm::error::ConvertError> {
+                        use ::exotherm::database::view::ColumnView;
+                        let res = #view {
+                            #(#from_archived),*
+                        };
+                        Ok(res)
+                    }
+                }
+            }
+        }
+        Some(view) => {
+            return Err(syn::Error::new_spanned(
+                view,
+                "views can not be generated for generic structs",
+            ));
+        }
+        None => quote! {},
+    };
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            #(#index_fns)*
+            #(#projections)*
+        }
+        impl #impl_generics ::exotherm::database::record::RecordStruct for #name #ty_generics #where_clause {
+            type Decoded = Self;
+            fn name() -> &'static str {
+                #table
+            }
+            fn corpus(&self) -> Vec<::exotherm::database::values_indices::DbValue> {
+                use ::exotherm::database::values_indices::*;
+                let unpadded = vec![#(#corpus),*];
+                ::exotherm::database::record::pad_indices(unpadded)
+            }
+            #[allow(unused_variables)]
+            fn indices(&self, row: ::exotherm::uuid::Uuid) -> Vec<::exotherm::database::key::Key> {
+                vec![#(#index_calls)*]
+            }
+            fn tname(&self) -> &'static str {
+                Self::name()
+            }
+            fn has_blobs() -> bool {
+                false #(#blob_columns)*
+            }
+            #[allow(unused_mut)]
+            fn blobs(&self) -> Vec<&::exotherm::database::blobstore::Blob> {
+                use ::exotherm::database::values_indices::DbValueEncode;
+                let mut blobs = Vec::new();
+                #(#blobs)*
+                blobs
+            }
+            fn deserialize(
+                mut from: Vec<::exotherm::database::values_indices::DbValue>,
+            ) -> Result<Self, ::exotherm::error::ConvertError> {
+                use ::exotherm::database::values_indices::DbValue;
+                let res = Self {
+                    #(#decode),*
+                };
+                Ok(res)
+            }
+        }
+        #patch
+        #view
+    })
+}
@@ -1,59 +1,248 @@
-use foundationdb::options::StreamingMode;
+use std::time::Duration;
+
+use foundationdb::{
+    KeySelector, RangeOption,
+    options::{MutationType, StreamingMode},
+};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
 use uuid::Uuid;
 
-use crate::error::SResult;
+use crate::{
+    database::{
+        content::ContentId,
+        database::{Database, now_millis},
+        key::{Key, Purpose, Tenant},
+        record::{BlobstoreAddress, RecordStruct},
+        transaction::STransaction,
+        values_indices::{DbValue, DbValueEncode, Row},
+    },
+    error::{ExothermError, SResult},
+};
+
+///Size of a single shard, FoundationDB values are limited to 100KB
+pub const SHARD_SIZE: usize = 50 * 1024;
+///Shards written or read per transaction by uploads and downloads, about 5MB
+pub(super) const SHARDS_PER_TRANSACTION: u32 = 100;
+///Bytes [`BlobUpload`] writes per transaction
+const BATCH_SIZE: usize = SHARD_SIZE * SHARDS_PER_TRANSACTION as usize;
+///Largest blob, shard numbers are stored as u16
+pub const MAX_BLOB_SIZE: u64 = (u16::MAX as u64 + 1) * SHARD_SIZE as u64;
+///Pending uploads checked per transaction by [`Database::collect_abandoned_uploads`]
+const ABANDONED_PAGE: usize = 100;
 
 pub struct ShardedBlob(pub Vec<u8>);
-#[derive(Debug, rkyv::Serialize, rkyv::Archive, rkyv::Deserialize)]
-pub struct BlobKey(String, Uuid);
+///Address of the shards of a blob, the bucket name can be chosen at runtime
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Serialize, rkyv::Archive, rkyv::Deserialize)]
+pub struct BlobKey(pub String, pub Uuid);
 
-#[allow(unused_variables)]
 impl BlobKey {
-    pub fn build_key(&self, tenant: &str) -> Vec<u8> {
-        todo!()
+    pub fn new(bucket: impl Into<String>, id: Uuid) -> Self {
+        BlobKey(bucket.into(), id)
+    }
+    pub fn bucket(&self) -> &str {
+        &self.0
+    }
+    pub fn id(&self) -> Uuid {
+        self.1
+    }
+    ///Key of one shard of the blob
+    pub fn build_key(&self, tenant: Tenant, shard: u16) -> SResult<Vec<u8>> {
+        Key::new_blob(tenant, self.0.as_str(), self.1, shard).generate()
+    }
+}
+
+///Tells where the data of a published blob is stored and how to verify it
+///
+/// Blobs are published by writing their manifest, so readers never see a partially written blob.
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Serialize, rkyv::Archive, rkyv::Deserialize)]
+pub struct BlobManifest {
+    ///Id the shards are stored under, a new one for every upload
+    pub data: Uuid,
+    ///Size before compression
+    pub size: u64,
+    pub shards: u32,
+    pub shard_size: u32,
+    ///SHA-256 of the whole blob, every shard additionally carries a crc32c
+    pub sha256: [u8; 32],
+    pub content_type: Option<String>,
+    pub compression: Compression,
+    ///Milliseconds since the epoch
+    pub created_at: u64,
+}
+
+impl BlobManifest {
+    ///Length of a shard after decompression
+    pub fn shard_len(&self, shard: u32) -> usize {
+        let start = shard as u64 * self.shard_size as u64;
+        self.size.saturating_sub(start).min(self.shard_size as u64) as usize
+    }
+}
+
+///How the shards of a blob are compressed, each one on its own so that ranges can still be read
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, rkyv::Serialize, rkyv::Archive, rkyv::Deserialize,
+)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+}
+
+///Metadata and compression of a blob that is written
+/// ```
+/// use exotherm::database::blobstore::{BlobOptions, Compression};
+/// let options = BlobOptions::new().content_type("image/png").zstd(3);
+/// assert_eq!(options.compression(), Compression::Zstd);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlobOptions {
+    content_type: Option<String>,
+    zstd_level: Option<i32>,
+}
+
+impl BlobOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = Some(content_type.into());
+        self
     }
+    ///Compress every shard with zstd, a level of 0 picks the zstd default
+    pub fn zstd(mut self, level: i32) -> Self {
+        self.zstd_level = Some(level);
+        self
+    }
+    pub fn compression(&self) -> Compression {
+        match self.zstd_level {
+            Some(_) => Compression::Zstd,
+            None => Compression::None,
+        }
+    }
+    fn manifest(&self, data: Uuid, size: u64, sha256: [u8; 32]) -> BlobManifest {
+        BlobManifest {
+            data,
+            size,
+            shards: size.div_ceil(SHARD_SIZE as u64) as u32,
+            shard_size: SHARD_SIZE as u32,
+            sha256,
+            content_type: self.content_type.clone(),
+            compression: self.compression(),
+            created_at: now_millis(),
+        }
+    }
+    ///Compress a shard if enabled and put the crc32c of the stored bytes in front
+    fn encode_shard(&self, shard: &[u8]) -> SResult<Vec<u8>> {
+        let payload = match self.zstd_level {
+            Some(level) => zstd::bulk::compress(shard, level)?,
+            None => shard.to_vec(),
+        };
+        let mut value = Vec::with_capacity(payload.len() + 4);
+        value.extend_from_slice(&crc32c::crc32c(&payload).to_be_bytes());
+        value.extend_from_slice(&payload);
+        Ok(value)
+    }
+}
+
+///Verify and decompress a stored shard, `None` if it is corrupted
+fn decode_shard(value: &[u8], compression: Compression, len: usize) -> Option<Vec<u8>> {
+    let (crc, payload) = value.split_first_chunk::<4>()?;
+    if u32::from_be_bytes(*crc) != crc32c::crc32c(payload) {
+        return None;
+    }
+    let shard = match compression {
+        Compression::None => payload.to_vec(),
+        Compression::Zstd => zstd::bulk::decompress(payload, len).ok()?,
+    };
+    (shard.len() == len).then_some(shard)
 }
-#[allow(dead_code)]
+
+#[derive(rkyv::Serialize, rkyv::Archive, rkyv::Deserialize)]
+struct PendingUpload {
+    bucket: String,
+    ///Renewed by every transaction of the upload
+    started_at: u64,
+}
+
+fn decode<T>(bytes: &[u8]) -> SResult<T>
+where
+    T: rkyv::Archive,
+    T::Archived: for<'a> rkyv::bytecheck::CheckBytes<rkyv::api::high::HighValidator<'a, rkyv::rancor::Error>>
+        + rkyv::Deserialize<T, rkyv::api::high::HighDeserializer<rkyv::rancor::Error>>,
+{
+    let mut aligned = rkyv::util::AlignedVec::<16>::with_capacity(bytes.len());
+    aligned.extend_from_slice(bytes);
+    let value = rkyv::from_bytes::<T, rkyv::rancor::Error>(&aligned)?;
+    Ok(value)
+}
+
 impl ShardedBlob {
-    pub async fn load(txn: &foundationdb::Transaction, key: Uuid) -> SResult<Self> {
-        //let key = Uuid::new_v4();
-        let mut start = key.as_bytes().to_vec();
-        let mut end = key.as_bytes().to_vec();
-        for b in usize::MIN.to_be_bytes() {
-            start.push(b);
-        }
-        for b in usize::MAX.to_be_bytes() {
-            end.push(b);
-        }
-        let mut opt = foundationdb::RangeOption::from((start, end));
-        opt.mode = StreamingMode::Serial;
-        let range = txn.get_range(&opt, 1000, true).await?;
-        let mut blob = Vec::<u8>::new();
-        for kv in range {
-            for byte in kv.value() {
-                blob.push(*byte);
+    ///Read shards `first..first + count` of a blob
+    pub async fn load_shards(
+        txn: &STransaction,
+        key: &BlobKey,
+        first: u16,
+        count: u32,
+    ) -> SResult<Vec<Vec<u8>>> {
+        let begin = key.build_key(txn.tenant.clone(), first)?;
+        let end = Key::blob_range(txn.tenant.clone(), key.bucket(), key.id())?.end;
+        let mut opt = RangeOption {
+            begin: KeySelector::first_greater_or_equal(begin.as_slice()),
+            end: KeySelector::first_greater_or_equal(end.as_slice()),
+            limit: Some(count as usize),
+            mode: StreamingMode::WantAll,
+            ..RangeOption::default()
+        };
+        let mut shards = Vec::new();
+        let mut iteration = 1;
+        loop {
+            let page = txn.trx.get_range(&opt, iteration, false).await?;
+            for kv in &page {
+                shards.push(kv.value().to_vec());
             }
+            match opt.next_range(&page) {
+                Some(next) => opt = next,
+                None => return Ok(shards),
+            }
+            iteration += 1;
         }
-        Ok(ShardedBlob(blob))
     }
-    pub async fn store(&self, txn: &foundationdb::Transaction, key: Uuid) -> SResult<()> {
-        let shards = self.shard();
-        for (key_id, shard) in shards.into_iter().enumerate() {
-            let mut combined_key = key.as_bytes().to_vec();
-            for byte in key_id.to_be_bytes() {
-                combined_key.push(byte);
-            }
-            txn.set(&combined_key, &shard);
+    ///Write the blob as shards starting at `first`
+    pub fn store_shards(
+        &self,
+        txn: &STransaction,
+        key: &BlobKey,
+        first: u32,
+        options: &BlobOptions,
+    ) -> SResult<u32> {
+        txn.writable()?;
+        let mut written = 0;
+        for shard in self.shard() {
+            let id = first + written;
+            let id = u16::try_from(id).map_err(|_| ExothermError::BlobTooLarge {
+                size: id as u64 * SHARD_SIZE as u64,
+                max: MAX_BLOB_SIZE,
+            })?;
+            txn.trx.set(
+                &key.build_key(txn.tenant.clone(), id)?,
+                &options.encode_shard(&shard)?,
+            );
+            written += 1;
         }
+        Ok(written)
+    }
+    pub fn delete(txn: &STransaction, key: &BlobKey) -> SResult<()> {
+        txn.writable()?;
+        let range = Key::blob_range(txn.tenant.clone(), key.bucket(), key.id())?;
+        txn.trx.clear_range(&range.begin, &range.end);
         Ok(())
     }
     pub fn shard(&self) -> Vec<Vec<u8>> {
         let ShardedBlob(data) = self;
 
-        let chunk_size = 50 * 1024; // 50 KB
-
         let chunks: Vec<Vec<u8>> = data
-            .chunks(chunk_size)
+            .chunks(SHARD_SIZE)
             .map(|chunk| chunk.to_vec())
             .collect();
 
@@ -71,3 +260,671 @@ impl ShardedBlob {
         ShardedBlob(buf)
     }
 }
+
+impl STransaction {
+    ///Store a blob in the tenant of the transaction, replacing an existing one
+    ///
+    /// Everything is written in this transaction, so the blob has to fit into its 10MB limit.
+    /// Use [`Database::upload_blob`] for larger blobs.
+    /// ```ignore
+    ///     transaction.put_blob("avatars", user_id, &png).await?;
+    /// ```
+    pub async fn put_blob(&self, bucket: &str, id: Uuid, data: &[u8]) -> SResult<BlobManifest> {
+        self.put_blob_with_options(bucket, id, data, &BlobOptions::default())
+            .await
+    }
+    ///Store a blob with a content type or compression
+    /// ```ignore
+    ///     let options = BlobOptions::new().content_type("text/csv").zstd(3);
+    ///     transaction.put_blob_with_options("exports", id, &csv, &options).await?;
+    /// ```
+    pub async fn put_blob_with_options(
+        &self,
+        bucket: &str,
+        id: Uuid,
+        data: &[u8],
+        options: &BlobOptions,
+    ) -> SResult<BlobManifest> {
+        let key = Key::new_blob_manifest(self.tenant.clone(), bucket, id).generate()?;
+        self.store_blob(bucket, &key, data, options).await
+    }
+    ///Read and verify a whole blob, which has to fit into the 5 second limit of the transaction
+    pub async fn get_blob(&self, bucket: &str, id: Uuid) -> SResult<Option<Vec<u8>>> {
+        let key = Key::new_blob_manifest(self.tenant.clone(), bucket, id).generate()?;
+        self.load_blob(bucket, id, &key).await
+    }
+    ///Delete a blob, returns whether it existed
+    pub async fn delete_blob(&self, bucket: &str, id: Uuid) -> SResult<bool> {
+        let key = Key::new_blob_manifest(self.tenant.clone(), bucket, id).generate()?;
+        self.remove_blob(bucket, &key).await
+    }
+    pub async fn get_blob_manifest(&self, bucket: &str, id: Uuid) -> SResult<Option<BlobManifest>> {
+        let key = Key::new_blob_manifest(self.tenant.clone(), bucket, id).generate()?;
+        self.read_manifest(&key).await
+    }
+    ///Write the shards of a blob and publish them under `manifest_key`
+    ///
+    /// The manifest key decides the namespace, see [`crate::database::content`] for another one than blob ids.
+    pub(super) async fn store_blob(
+        &self,
+        bucket: &str,
+        manifest_key: &[u8],
+        data: &[u8],
+        options: &BlobOptions,
+    ) -> SResult<BlobManifest> {
+        let sha256 = Sha256::digest(data).into();
+        let manifest = options.manifest(Uuid::new_v4(), data.len() as u64, sha256);
+        let blob = ShardedBlob(data.to_vec());
+        blob.store_shards(self, &BlobKey::new(bucket, manifest.data), 0, options)?;
+        self.publish_manifest(bucket, manifest_key, &manifest)
+            .await?;
+        Ok(manifest)
+    }
+    ///Read and verify the blob published under `manifest_key`, `id` is only used in errors
+    pub(super) async fn load_blob(
+        &self,
+        bucket: &str,
+        id: Uuid,
+        manifest_key: &[u8],
+    ) -> SResult<Option<Vec<u8>>> {
+        let Some(manifest) = self.read_manifest(manifest_key).await? else {
+            return Ok(None);
+        };
+        let key = BlobKey::new(bucket, manifest.data);
+        let shards = ShardedBlob::load_shards(self, &key, 0, manifest.shards).await?;
+        let data = decode_shards(bucket, id, &manifest, 0, manifest.shards, shards)?;
+        verify_digest(bucket, id, &manifest, &data)?;
+        Ok(Some(data))
+    }
+    pub(super) async fn remove_blob(&self, bucket: &str, manifest_key: &[u8]) -> SResult<bool> {
+        self.writable()?;
+        let Some(manifest) = self.read_manifest(manifest_key).await? else {
+            return Ok(false);
+        };
+        ShardedBlob::delete(self, &BlobKey::new(bucket, manifest.data))?;
+        self.trx.clear(manifest_key);
+        Ok(true)
+    }
+    pub(super) async fn read_manifest(&self, manifest_key: &[u8]) -> SResult<Option<BlobManifest>> {
+        match self.trx.get(manifest_key, false).await? {
+            Some(value) => Ok(Some(decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+    ///Point a blob to new data, the data of the previous version is deleted
+    async fn publish_blob(&self, bucket: &str, id: Uuid, manifest: &BlobManifest) -> SResult<()> {
+        let key = Key::new_blob_manifest(self.tenant.clone(), bucket, id).generate()?;
+        self.publish_manifest(bucket, &key, manifest).await
+    }
+    async fn publish_manifest(
+        &self,
+        bucket: &str,
+        manifest_key: &[u8],
+        manifest: &BlobManifest,
+    ) -> SResult<()> {
+        self.writable()?;
+        if let Some(old) = self.read_manifest(manifest_key).await?
+            && old.data != manifest.data
+        {
+            ShardedBlob::delete(self, &BlobKey::new(bucket, old.data))?;
+        }
+        let value = rkyv::to_bytes::<rkyv::rancor::Error>(manifest)?;
+        self.trx.set(manifest_key, &value);
+        let pending = Key::new_blob_pending(self.tenant.clone(), manifest.data).generate()?;
+        self.trx.clear(&pending);
+        Ok(())
+    }
+    ///Check that an upload was not collected as abandoned and renew its start time
+    ///
+    /// The read conflicts with [`Database::collect_abandoned_uploads`], so only one of them commits.
+    async fn renew_upload(&self, bucket: &str, id: Uuid, data: Uuid) -> SResult<()> {
+        self.writable()?;
+        let key = Key::new_blob_pending(self.tenant.clone(), data).generate()?;
+        if self.trx.get(&key, false).await?.is_none() {
+            return Err(ExothermError::UploadAbandoned {
+                bucket: bucket.to_string(),
+                id,
+            });
+        }
+        let pending = PendingUpload {
+            bucket: bucket.to_string(),
+            started_at: now_millis(),
+        };
+        let value = rkyv::to_bytes::<rkyv::rancor::Error>(&pending)?;
+        self.trx.set(&key, &value);
+        Ok(())
+    }
+}
+
+///Verify and join shards, they are missing if the blob was replaced during a chunked download
+fn decode_shards(
+    bucket: &str,
+    id: Uuid,
+    manifest: &BlobManifest,
+    first: u32,
+    count: u32,
+    shards: Vec<Vec<u8>>,
+) -> SResult<Vec<u8>> {
+    let expected = count.min(manifest.shards.saturating_sub(first));
+    if shards.len() as u32 != expected {
+        return Err(ExothermError::BlobChanged {
+            bucket: bucket.to_string(),
+            id,
+        });
+    }
+    let mut data = Vec::with_capacity(expected as usize * manifest.shard_size as usize);
+    for (shard, value) in (first..).zip(&shards) {
+        let decoded = decode_shard(value, manifest.compression, manifest.shard_len(shard))
+            .ok_or_else(|| ExothermError::BlobCorrupted {
+                bucket: bucket.to_string(),
+                id,
+                shard: Some(shard),
+            })?;
+        data.extend_from_slice(&decoded);
+    }
+    Ok(data)
+}
+
+///Compare a whole blob with the SHA-256 of its manifest
+fn verify_digest(bucket: &str, id: Uuid, manifest: &BlobManifest, data: &[u8]) -> SResult<()> {
+    if Sha256::digest(data).as_slice() != manifest.sha256 {
+        return Err(ExothermError::BlobCorrupted {
+            bucket: bucket.to_string(),
+            id,
+            shard: None,
+        });
+    }
+    Ok(())
+}
+
+///Upload that is spread over several transactions, created by [`Database::begin_blob_upload`]
+///
+/// Shards are written under a fresh data id and only become visible when [`Self::finish`] publishes the manifest.
+/// Uploads that are neither finished nor aborted are removed by [`Database::collect_abandoned_uploads`].
+pub struct BlobUpload<'a> {
+    db: &'a Database,
+    bucket: String,
+    id: Uuid,
+    data: Uuid,
+    size: u64,
+    shards: u32,
+    buffer: Vec<u8>,
+    options: BlobOptions,
+    hasher: Sha256,
+}
+
+impl BlobUpload<'_> {
+    ///Bytes that can be buffered before [`Self::write`] has to write a batch of shards
+    pub(super) fn remaining_batch(&self) -> usize {
+        BATCH_SIZE.saturating_sub(self.buffer.len())
+    }
+    ///Buffer data that does not fill up the batch, see [`Self::remaining_batch`]
+    pub(super) fn buffer(&mut self, data: &[u8]) {
+        debug_assert!(data.len() < self.remaining_batch());
+        self.hasher.update(data);
+        self.buffer.extend_from_slice(data);
+    }
+    ///Buffer data and write every full batch of shards
+    ///
+    /// If writing a batch fails, `data` stays buffered. Do not write it again, a later
+    /// [`Self::write`] or [`Self::finish`] retries the batch.
+    pub async fn write(&mut self, data: &[u8]) -> SResult<()> {
+        self.hasher.update(data);
+        self.buffer.extend_from_slice(data);
+        while self.buffer.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+    ///Write the remaining data and publish the blob
+    ///
+    /// Full batches left by a failed [`Self::write`] are written first, the rest fits into the publishing transaction.
+    pub async fn finish(mut self) -> SResult<BlobManifest> {
+        while self.buffer.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+        let size = self.size + self.buffer.len() as u64;
+        let manifest = self
+            .options
+            .manifest(self.data, size, self.hasher.finalize().into());
+        let (bucket, id, data) = (self.bucket.as_str(), self.id, self.data);
+        let (buffer, options) = (&self.buffer, &self.options);
+        let first = self.shards;
+        self.db
+            .transact(|transaction| {
+                let manifest = &manifest;
+                async move {
+                    transaction.renew_upload(bucket, id, data).await?;
+                    let blob = ShardedBlob(buffer.clone());
+                    let key = BlobKey::new(bucket, data);
+                    blob.store_shards(&transaction, &key, first, options)?;
+                    transaction.publish_blob(bucket, id, manifest).await
+                }
+            })
+            .await?;
+        Ok(manifest)
+    }
+    ///Delete what was written so far
+    pub async fn abort(self) -> SResult<()> {
+        let (bucket, data) = (self.bucket.as_str(), self.data);
+        self.db
+            .transact(|transaction| async move {
+                ShardedBlob::delete(&transaction, &BlobKey::new(bucket, data))?;
+                let pending = Key::new_blob_pending(transaction.tenant.clone(), data).generate()?;
+                transaction.trx.clear(&pending);
+                Ok(())
+            })
+            .await
+    }
+    ///Write the first batch of the buffer in one transaction, it is only removed from the buffer once committed
+    async fn flush(&mut self) -> SResult<()> {
+        let (bucket, id, data, first) = (self.bucket.as_str(), self.id, self.data, self.shards);
+        let (buffer, options) = (&self.buffer[..BATCH_SIZE], &self.options);
+        let written = self
+            .db
+            .transact(|transaction| async move {
+                transaction.renew_upload(bucket, id, data).await?;
+                let blob = ShardedBlob(buffer.to_vec());
+                blob.store_shards(&transaction, &BlobKey::new(bucket, data), first, options)
+            })
+            .await?;
+        self.shards += written;
+        self.size += BATCH_SIZE as u64;
+        self.buffer.drain(..BATCH_SIZE);
+        Ok(())
+    }
+}
+
+impl Database {
+    ///Start an upload for a blob of any size up to [`MAX_BLOB_SIZE`]
+    /// ```ignore
+    ///     let mut upload = db.begin_blob_upload("videos", id).await?;
+    ///     while let Some(chunk) = body.next().await {
+    ///         upload.write(&chunk?).await?;
+    ///     }
+    ///     upload.finish().await?;
+    /// ```
+    pub async fn begin_blob_upload(&self, bucket: &str, id: Uuid) -> SResult<BlobUpload<'_>> {
+        self.begin_blob_upload_with_options(bucket, id, &BlobOptions::default())
+            .await
+    }
+    ///Start an upload with a content type or compression
+    pub async fn begin_blob_upload_with_options(
+        &self,
+        bucket: &str,
+        id: Uuid,
+        options: &BlobOptions,
+    ) -> SResult<BlobUpload<'_>> {
+        let data = Uuid::new_v4();
+        self.transact(|transaction| async move {
+            transaction.writable()?;
+            let pending = PendingUpload {
+                bucket: bucket.to_string(),
+                started_at: now_millis(),
+            };
+            let key = Key::new_blob_pending(transaction.tenant.clone(), data).generate()?;
+            let value = rkyv::to_bytes::<rkyv::rancor::Error>(&pending)?;
+            transaction.trx.set(&key, &value);
+            Ok(())
+        })
+        .await?;
+        Ok(BlobUpload {
+            db: self,
+            bucket: bucket.to_string(),
+            id,
+            data,
+            size: 0,
+            shards: 0,
+            buffer: Vec::new(),
+            options: options.clone(),
+            hasher: Sha256::new(),
+        })
+    }
+    ///Upload a blob that may be larger than a single transaction
+    pub async fn upload_blob(&self, bucket: &str, id: Uuid, data: &[u8]) -> SResult<BlobManifest> {
+        self.upload_blob_with_options(bucket, id, data, &BlobOptions::default())
+            .await
+    }
+    pub async fn upload_blob_with_options(
+        &self,
+        bucket: &str,
+        id: Uuid,
+        data: &[u8],
+        options: &BlobOptions,
+    ) -> SResult<BlobManifest> {
+        let mut upload = self
+            .begin_blob_upload_with_options(bucket, id, options)
+            .await?;
+        upload.write(data).await?;
+        upload.finish().await
+    }
+    pub async fn get_blob_manifest(&self, bucket: &str, id: Uuid) -> SResult<Option<BlobManifest>> {
+        self.transact(|transaction| async move { transaction.get_blob_manifest(bucket, id).await })
+            .await
+    }
+    ///Read and verify `count` shards of a blob starting at `first`, fails if the blob was replaced in the meantime
+    pub async fn read_blob_shards(
+        &self,
+        bucket: &str,
+        id: Uuid,
+        manifest: &BlobManifest,
+        first: u32,
+        count: u32,
+    ) -> SResult<Vec<u8>> {
+        let key = BlobKey::new(bucket, manifest.data);
+        let start = u16::try_from(first).unwrap_or(u16::MAX);
+        let shards = self
+            .transact(|transaction| {
+                let key = &key;
+                async move { ShardedBlob::load_shards(&transaction, key, start, count).await }
+            })
+            .await?;
+        decode_shards(bucket, id, manifest, first, count, shards)
+    }
+    ///Download and verify a blob in several transactions
+    pub async fn download_blob(&self, bucket: &str, id: Uuid) -> SResult<Option<Vec<u8>>> {
+        let Some(manifest) = self.get_blob_manifest(bucket, id).await? else {
+            return Ok(None);
+        };
+        let mut data = Vec::with_capacity(manifest.size as usize);
+        let mut first = 0;
+        while first < manifest.shards {
+            let chunk = self
+                .read_blob_shards(bucket, id, &manifest, first, SHARDS_PER_TRANSACTION)
+                .await?;
+            data.extend_from_slice(&chunk);
+            first += SHARDS_PER_TRANSACTION;
+        }
+        verify_digest(bucket, id, &manifest, &data)?;
+        Ok(Some(data))
+    }
+    ///Delete the data of uploads that have not written anything for `older_than` and were never finished
+    ///
+    /// Every transaction of an upload renews it, uploads that are collected while they are still
+    /// running fail with [`ExothermError::UploadAbandoned`]. Returns the number of removed uploads.
+    pub async fn collect_abandoned_uploads(&self, older_than: Duration) -> SResult<usize> {
+        let deadline = now_millis().saturating_sub(older_than.as_millis() as u64);
+        let range = Key::purpose_range(self.tenant(), "", Purpose::BLOB_PENDING_TAG)?;
+        let mut begin = range.begin.clone();
+        let mut removed = 0;
+        loop {
+            let (page, next) = self
+                .transact(|transaction| {
+                    let (begin, end) = (begin.as_slice(), range.end.as_slice());
+                    async move {
+                        transaction.writable()?;
+                        let opt = RangeOption {
+                            limit: Some(ABANDONED_PAGE),
+                            mode: StreamingMode::WantAll,
+                            ..RangeOption::from((begin, end))
+                        };
+                        let values = transaction.trx.get_range(&opt, 1, false).await?;
+                        let mut removed = 0;
+                        for kv in &values {
+                            let pending: PendingUpload = decode(kv.value())?;
+                            if pending.started_at > deadline {
+                                continue;
+                            }
+                            let data = Uuid::from_slice(&kv.key()[kv.key().len() - 16..])?;
+                            ShardedBlob::delete(&transaction, &BlobKey::new(pending.bucket, data))?;
+                            transaction.trx.clear(kv.key());
+                            removed += 1;
+                        }
+                        //Continue right after the last key of the page
+                        let next = values.more().then(|| {
+                            let mut next = values[values.len() - 1].key().to_vec();
+                            next.push(0);
+                            next
+                        });
+                        Ok((removed, next))
+                    }
+                })
+                .await?;
+            removed += page;
+            match next {
+                Some(next) => begin = next,
+                None => return Ok(removed),
+            }
+        }
+    }
+}
+
+///Column type that keeps its data in the blobstore, the row only stores the [`BlobstoreAddress`]
+///
+/// The data of a new blob is written when the row is put. Rows count their references to a blob,
+/// so copies of a record share the data, which is deleted when the last referencing row is cleared
+/// or written with a different blob in the column. Blobs of rows that were read load their data on first access.
+/// ```ignore
+/// schema!(Document {
+///     0 -> title: [] String,
+///     1 -> body: [] Blob,
+/// });
+///     let document = Document { title, body: Blob::new("documents", pdf) };
+///     transaction.put_value(&document, id).await?;
+///     let document: Document = transaction.get_value(id).await?.unwrap();
+///     let pdf = document.body.data(&transaction).await?;
+/// ```
+/// Everything is written in the transaction of the row, so the data has to fit into its 10MB limit.
+/// Use [`Blob::deduplicated`] to store identical data of different rows only once.
+#[derive(Debug, Clone)]
+pub struct Blob {
+    address: BlobstoreAddress,
+    ///Set for deduplicated blobs, whose data is stored as content and not under the id of the address
+    content: Option<ContentId>,
+    data: OnceCell<Vec<u8>>,
+    ///Whether `data` was set by the user and still has to be written
+    pending: bool,
+}
+
+///Reference of a row to a blob, rows count one reference per column
+type BlobRef = (BlobstoreAddress, Option<ContentId>);
+
+impl Blob {
+    ///New blob with a random id in `bucket`
+    pub fn new(bucket: impl Into<String>, data: Vec<u8>) -> Self {
+        let address = BlobstoreAddress {
+            bucket: bucket.into(),
+            id: Uuid::new_v4(),
+        };
+        Blob {
+            address,
+            content: None,
+            data: OnceCell::new_with(Some(data)),
+            pending: true,
+        }
+    }
+    ///New blob that is stored once per bucket for all rows with the same data, see [`crate::database::content`]
+    pub fn deduplicated(bucket: impl Into<String>, data: Vec<u8>) -> Self {
+        let content = ContentId::of(&data);
+        let address = BlobstoreAddress {
+            bucket: bucket.into(),
+            id: content.id(),
+        };
+        Blob {
+            address,
+            content: Some(content),
+            data: OnceCell::new_with(Some(data)),
+            pending: true,
+        }
+    }
+    ///Blob that was read from a row, its data is loaded on first access
+    pub fn stored(address: BlobstoreAddress) -> Self {
+        Blob {
+            address,
+            content: None,
+            data: OnceCell::new(),
+            pending: false,
+        }
+    }
+    ///Deduplicated blob that was read from a row
+    pub fn stored_deduplicated(address: BlobstoreAddress, content: ContentId) -> Self {
+        Blob {
+            content: Some(content),
+            ..Self::stored(address)
+        }
+    }
+    pub fn address(&self) -> &BlobstoreAddress {
+        &self.address
+    }
+    ///Hash the data is stored under if the blob is deduplicated
+    pub fn content_id(&self) -> Option<&ContentId> {
+        self.content.as_ref()
+    }
+    ///Data that is written when the row is put
+    pub fn pending(&self) -> Option<&[u8]> {
+        self.data.get().filter(|_| self.pending).map(Vec::as_slice)
+    }
+    ///Data of the blob, read with `transaction` on the first call
+    pub async fn data(&self, transaction: &STransaction) -> SResult<&[u8]> {
+        let data = self
+            .data
+            .get_or_try_init(|| async {
+                let BlobstoreAddress { bucket, id } = &self.address;
+                let data = match &self.content {
+                    Some(content) => transaction.get_content(bucket, content).await?,
+                    None => transaction.get_blob(bucket, *id).await?,
+                };
+                data.ok_or_else(|| ExothermError::BlobNotFound {
+                    bucket: bucket.clone(),
+                    id: *id,
+                })
+            })
+            .await?;
+        Ok(data)
+    }
+    fn reference(&self) -> BlobRef {
+        (self.address.clone(), self.content)
+    }
+}
+
+impl DbValueEncode for Blob {
+    const BLOB: bool = true;
+    fn encode_db(&self) -> DbValue {
+        match self.content {
+            Some(content) => DbValue::ContentAddress(self.address.clone(), content),
+            None => DbValue::BlobAddress(self.address.clone()),
+        }
+    }
+    fn blob(&self) -> Option<&Blob> {
+        Some(self)
+    }
+}
+
+impl STransaction {
+    ///Write new blobs of a row and release the ones only the previous version of the row referenced
+    pub(super) async fn swap_blobs(&self, old: Option<&[u8]>, new: &[&Blob]) -> SResult<()> {
+        let old_refs = match old {
+            Some(old) => blob_refs(old)?,
+            None => Vec::new(),
+        };
+        let new_refs: Vec<BlobRef> = new.iter().map(|blob| blob.reference()).collect();
+        for (blob, reference) in new.iter().zip(&new_refs) {
+            if !old_refs.contains(reference) {
+                self.retain_reference(blob).await?;
+            }
+        }
+        for reference in &old_refs {
+            if !new_refs.contains(reference) {
+                self.release_reference(reference).await?;
+            }
+        }
+        Ok(())
+    }
+    ///Release the blobs of a row that is cleared
+    pub(super) async fn release_blobs(&self, blobs: &[&Blob]) -> SResult<()> {
+        for blob in blobs {
+            self.release_reference(&blob.reference()).await?;
+        }
+        Ok(())
+    }
+    ///Release the blobs of up to `limit` rows of a table and clear those rows, returns whether rows are left
+    pub(super) async fn release_table_blobs<T: RecordStruct>(&self, limit: usize) -> SResult<bool> {
+        self.writable()?;
+        let range = Key::purpose_range(self.tenant.clone(), T::name(), Purpose::ROW_TAG)?;
+        let opt = RangeOption {
+            limit: Some(limit),
+            mode: StreamingMode::WantAll,
+            ..RangeOption::from((range.begin.as_slice(), range.end.as_slice()))
+        };
+        let rows = self.trx.get_range(&opt, 1, false).await?;
+        for kv in &rows {
+            for reference in blob_refs(kv.value())? {
+                self.release_reference(&reference).await?;
+            }
+            self.trx.clear(kv.key());
+        }
+        Ok(rows.more())
+    }
+    ///Add a reference of a row, writing the data of a pending blob unless it already exists
+    async fn retain_reference(&self, blob: &Blob) -> SResult<()> {
+        let BlobstoreAddress { bucket, id } = blob.address();
+        match (blob.content_id(), blob.pending()) {
+            (Some(_), Some(data)) => {
+                self.put_content(bucket, data).await?;
+            }
+            (Some(content), None) => self.retain_content(bucket, content)?,
+            (None, data) => {
+                //A pending blob that was put under another row before only needs another reference
+                if let Some(data) = data
+                    && self.get_blob_manifest(bucket, *id).await?.is_none()
+                {
+                    self.put_blob(bucket, *id, data).await?;
+                }
+                self.retain_blob(bucket, *id)?;
+            }
+        }
+        Ok(())
+    }
+    async fn release_reference(&self, (address, content): &BlobRef) -> SResult<()> {
+        match content {
+            Some(content) => {
+                self.release_content(&address.bucket, content).await?;
+            }
+            None => self.release_blob(&address.bucket, address.id).await?,
+        }
+        Ok(())
+    }
+    ///Add a reference of a row to the blob of a [`Blob`] column
+    fn retain_blob(&self, bucket: &str, id: Uuid) -> SResult<()> {
+        let key = Key::new_blob_refs(self.tenant.clone(), bucket, id).generate()?;
+        self.trx
+            .atomic_op(&key, &1u64.to_le_bytes(), MutationType::Add);
+        Ok(())
+    }
+    ///Remove a reference of a row, the blob is deleted with the last one
+    async fn release_blob(&self, bucket: &str, id: Uuid) -> SResult<()> {
+        let key = Key::new_blob_refs(self.tenant.clone(), bucket, id).generate()?;
+        let refs = match self.trx.get(&key, false).await? {
+            Some(value) => decode_count(&value),
+            None => 0,
+        };
+        if refs > 1 {
+            self.trx
+                .atomic_op(&key, &u64::MAX.to_le_bytes(), MutationType::Add);
+            return Ok(());
+        }
+        self.trx.clear(&key);
+        self.delete_blob(bucket, id).await?;
+        Ok(())
+    }
+}
+
+///Blobs a stored row references
+fn blob_refs(row: &[u8]) -> SResult<Vec<BlobRef>> {
+    let Row(values) = decode(row)?;
+    Ok(values
+        .into_iter()
+        .filter_map(|value| match value {
+            DbValue::BlobAddress(address) => Some((address, None)),
+            DbValue::ContentAddress(address, content) => Some((address, Some(content))),
+            _ => None,
+        })
+        .collect())
+}
+
+///Little endian counter updated with [`MutationType::Add`], which may be stored with fewer than 8 bytes
+pub(super) fn decode_count(value: &[u8]) -> u64 {
+    let (mut bytes, len) = ([0; 8], value.len().min(8));
+    bytes[..len].copy_from_slice(&value[..len]);
+    u64::from_le_bytes(bytes)
+}
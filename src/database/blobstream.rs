@@ -0,0 +1,396 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        blobstore::{BlobManifest, BlobOptions, BlobUpload},
+        database::Database,
+    },
+    error::{ExothermError, SResult},
+};
+
+///Shards fetched by one read of a [`BlobReader`], about 800KB
+const SHARDS_PER_READ: u32 = 16;
+
+///Single byte range of an HTTP `Range` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    ///`bytes=500-`, everything from the offset on
+    From(u64),
+    ///`bytes=0-499`, both ends are included
+    Inclusive(u64, u64),
+    ///`bytes=-500`, the last bytes of the blob
+    Suffix(u64),
+}
+
+impl ByteRange {
+    ///Parse a `Range` header, requests for several ranges are not supported
+    pub fn parse(header: &str) -> Option<Self> {
+        let range = header.trim().strip_prefix("bytes=")?;
+        let (start, end) = range.trim().split_once('-')?;
+        match (start.trim(), end.trim()) {
+            ("", "") => None,
+            ("", suffix) => Some(Self::Suffix(suffix.parse().ok()?)),
+            (start, "") => Some(Self::From(start.parse().ok()?)),
+            (start, end) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(Self::Inclusive(start, end))
+            }
+        }
+    }
+    ///Offset and length of the range in a blob of `size` bytes, `None` if it can not be satisfied
+    pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        match *self {
+            Self::From(start) if start < size => Some((start, size - start)),
+            Self::Inclusive(start, end) if start < size => {
+                Some((start, end.min(size - 1) - start + 1))
+            }
+            Self::Suffix(len) if len > 0 && size > 0 => {
+                let len = len.min(size);
+                Some((size - len, len))
+            }
+            _ => None,
+        }
+    }
+}
+
+///Reads a blob over several transactions, created by [`Database::open_blob`]
+///
+/// Shards are only fetched when they are read, so seeking to the end of a large blob is cheap.
+/// If the blob is replaced while it is being read, reads fail with [`ExothermError::BlobChanged`].
+/// Shards are checked against their crc32c. A reader without a range that reads the blob from start
+/// to end without seeking also verifies the SHA-256 of the whole blob and fails at the end if it differs.
+/// ```ignore
+///     let Some(reader) = db.open_blob("videos", id).await? else {
+///         return Ok(not_found());
+///     };
+///     let reader = match ByteRange::parse(header) {
+///         Some(range) => reader.range(range)?,
+///         None => reader,
+///     };
+///     tokio::io::copy(&mut reader, &mut response).await?;
+/// ```
+pub struct BlobReader<'a> {
+    db: &'a Database,
+    bucket: String,
+    id: Uuid,
+    manifest: BlobManifest,
+    ///Start of the readable range in the blob
+    offset: u64,
+    len: u64,
+    ///Position relative to `offset`
+    position: u64,
+    ///Fetched bytes and where they start in the blob
+    chunk: Vec<u8>,
+    chunk_start: u64,
+    fetch: Option<BoxFuture<'a, SResult<(u64, Vec<u8>)>>>,
+    ///Digest of the bytes read so far, dropped once reads are not sequential from the start
+    digest: Option<Sha256>,
+    hashed: u64,
+}
+
+impl<'a> BlobReader<'a> {
+    ///Only read `range`, positions and seeks are relative to its start afterwards
+    pub fn range(mut self, range: ByteRange) -> SResult<Self> {
+        let (offset, len) =
+            range
+                .resolve(self.manifest.size)
+                .ok_or(ExothermError::RangeNotSatisfiable {
+                    size: self.manifest.size,
+                })?;
+        self.offset = offset;
+        self.len = len;
+        self.position = 0;
+        self.digest = None;
+        Ok(self)
+    }
+    pub fn manifest(&self) -> &BlobManifest {
+        &self.manifest
+    }
+    ///Start of the readable range in the blob
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+    ///Length of the readable range
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    ///Value of a `Content-Range` header for the readable range, e.g. `bytes 0-499/1234`
+    pub fn content_range(&self) -> String {
+        match self.len {
+            0 => format!("bytes */{}", self.manifest.size),
+            len => format!(
+                "bytes {}-{}/{}",
+                self.offset,
+                self.offset + len - 1,
+                self.manifest.size
+            ),
+        }
+    }
+    ///Fetch the shards starting with the one containing `at`, up to the end of the range
+    fn fetch_at(&self, at: u64) -> BoxFuture<'a, SResult<(u64, Vec<u8>)>> {
+        let shard_size = self.manifest.shard_size as u64;
+        let first = (at / shard_size) as u32;
+        let last = ((self.offset + self.len - 1) / shard_size) as u32;
+        let count = (last - first + 1).min(SHARDS_PER_READ);
+        let (db, bucket, id, manifest) =
+            (self.db, self.bucket.clone(), self.id, self.manifest.clone());
+        Box::pin(async move {
+            let data = db
+                .read_blob_shards(&bucket, id, &manifest, first, count)
+                .await?;
+            Ok((first as u64 * shard_size, data))
+        })
+    }
+    ///Compare the digest of a blob read from start to end with its manifest, only once
+    fn verify(&mut self) -> io::Result<()> {
+        match self.digest.take() {
+            Some(digest)
+                if self.hashed == self.manifest.size
+                    && digest.finalize().as_slice() != self.manifest.sha256 =>
+            {
+                Err(io::Error::other(ExothermError::BlobCorrupted {
+                    bucket: self.bucket.clone(),
+                    id: self.id,
+                    shard: None,
+                }))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl AsyncRead for BlobReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.position >= this.len {
+                return Poll::Ready(this.verify());
+            }
+            if buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            let at = this.offset + this.position;
+            let chunk_end = this.chunk_start + this.chunk.len() as u64;
+            if (this.chunk_start..chunk_end).contains(&at) {
+                let n = (buf.remaining() as u64)
+                    .min(chunk_end - at)
+                    .min(this.len - this.position) as usize;
+                let from = (at - this.chunk_start) as usize;
+                let read = &this.chunk[from..from + n];
+                match &mut this.digest {
+                    Some(digest) if at == this.hashed => {
+                        digest.update(read);
+                        this.hashed += n as u64;
+                    }
+                    _ => this.digest = None,
+                }
+                buf.put_slice(read);
+                this.position += n as u64;
+                return Poll::Ready(Ok(()));
+            }
+            let fetch = match this.fetch.take() {
+                Some(fetch) => fetch,
+                None => this.fetch_at(at),
+            };
+            let result = ready!(this.fetch.insert(fetch).as_mut().poll(cx));
+            this.fetch = None;
+            let (start, chunk) = result.map_err(io::Error::other)?;
+            if chunk.is_empty() {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.chunk = chunk;
+            this.chunk_start = start;
+        }
+    }
+}
+
+impl AsyncSeek for BlobReader<'_> {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        let position = match position {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(())
+    }
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+enum WriterState<'a> {
+    Idle(BlobUpload<'a>),
+    Writing(BoxFuture<'a, (BlobUpload<'a>, SResult<()>)>),
+    Finishing(BoxFuture<'a, SResult<BlobManifest>>),
+    Finished(BlobManifest),
+    Failed,
+}
+
+///Uploads a blob as data arrives, created by [`Database::blob_writer`]
+///
+/// Data is buffered until a batch of shards can be written in one transaction.
+/// The blob is published by `shutdown`, dropping the writer leaves an abandoned upload
+/// for [`Database::collect_abandoned_uploads`].
+/// ```ignore
+///     let mut writer = db.blob_writer("videos", id).await?;
+///     tokio::io::copy(&mut request_body, &mut writer).await?;
+///     writer.shutdown().await?;
+/// ```
+pub struct BlobWriter<'a> {
+    state: WriterState<'a>,
+}
+
+impl BlobWriter<'_> {
+    ///Manifest of the published blob, once the writer was shut down
+    pub fn manifest(&self) -> Option<&BlobManifest> {
+        match &self.state {
+            WriterState::Finished(manifest) => Some(manifest),
+            _ => None,
+        }
+    }
+    ///Wait for a running batch write
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.state {
+            WriterState::Idle(_) => Poll::Ready(Ok(())),
+            WriterState::Writing(write) => {
+                //A failed batch loses the buffered data, so the upload can not continue
+                let (upload, result) = ready!(write.as_mut().poll(cx));
+                self.state = match result {
+                    Ok(()) => WriterState::Idle(upload),
+                    Err(_) => WriterState::Failed,
+                };
+                Poll::Ready(result.map_err(io::Error::other))
+            }
+            WriterState::Finishing(_) | WriterState::Finished(_) | WriterState::Failed => {
+                Poll::Ready(Err(io::Error::other("blob writer was shut down")))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for BlobWriter<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.poll_idle(cx))?;
+        let WriterState::Idle(upload) = &mut self.state else {
+            unreachable!("poll_idle returned without an idle upload");
+        };
+        let remaining = upload.remaining_batch();
+        if buf.len() < remaining {
+            upload.buffer(buf);
+            return Poll::Ready(Ok(buf.len()));
+        }
+        //The write completes the batch, which is written in the background
+        let WriterState::Idle(mut upload) = std::mem::replace(&mut self.state, WriterState::Failed)
+        else {
+            unreachable!();
+        };
+        let data = buf[..remaining].to_vec();
+        self.state = WriterState::Writing(Box::pin(async move {
+            let result = upload.write(&data).await;
+            (upload, result)
+        }));
+        Poll::Ready(Ok(remaining))
+    }
+    ///Waits for a running batch write, the data is not visible before `shutdown`
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.state {
+            WriterState::Finished(_) => Poll::Ready(Ok(())),
+            _ => self.poll_idle(cx),
+        }
+    }
+    ///Writes the rest of the data and publishes the blob
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match &mut self.state {
+                WriterState::Idle(_) => {
+                    let WriterState::Idle(upload) =
+                        std::mem::replace(&mut self.state, WriterState::Failed)
+                    else {
+                        unreachable!();
+                    };
+                    self.state = WriterState::Finishing(Box::pin(upload.finish()));
+                }
+                WriterState::Writing(_) => ready!(self.poll_idle(cx))?,
+                WriterState::Finishing(finish) => {
+                    let result = ready!(finish.as_mut().poll(cx));
+                    return Poll::Ready(match result {
+                        Ok(manifest) => {
+                            self.state = WriterState::Finished(manifest);
+                            Ok(())
+                        }
+                        Err(error) => {
+                            self.state = WriterState::Failed;
+                            Err(io::Error::other(error))
+                        }
+                    });
+                }
+                WriterState::Finished(_) => return Poll::Ready(Ok(())),
+                WriterState::Failed => {
+                    return Poll::Ready(Err(io::Error::other("blob upload failed")));
+                }
+            }
+        }
+    }
+}
+
+impl Database {
+    ///Open a blob for streaming reads, `None` if it does not exist
+    pub async fn open_blob(&self, bucket: &str, id: Uuid) -> SResult<Option<BlobReader<'_>>> {
+        let Some(manifest) = self.get_blob_manifest(bucket, id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(BlobReader {
+            db: self,
+            bucket: bucket.to_string(),
+            id,
+            offset: 0,
+            len: manifest.size,
+            position: 0,
+            chunk: Vec::new(),
+            chunk_start: 0,
+            fetch: None,
+            digest: Some(Sha256::new()),
+            hashed: 0,
+            manifest,
+        }))
+    }
+    ///Start a streaming upload, the blob replaces an existing one when the writer is shut down
+    pub async fn blob_writer(&self, bucket: &str, id: Uuid) -> SResult<BlobWriter<'_>> {
+        self.blob_writer_with_options(bucket, id, &BlobOptions::default())
+            .await
+    }
+    pub async fn blob_writer_with_options(
+        &self,
+        bucket: &str,
+        id: Uuid,
+        options: &BlobOptions,
+    ) -> SResult<BlobWriter<'_>> {
+        let upload = self
+            .begin_blob_upload_with_options(bucket, id, options)
+            .await?;
+        Ok(BlobWriter {
+            state: WriterState::Idle(upload),
+        })
+    }
+}
@@ -0,0 +1,284 @@
+use futures::{StreamExt, stream};
+use rkyv::util::AlignedVec;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    database::{database::Database, record::RecordStruct},
+    error::{ExothermError, SResult},
+};
+
+///Settings for [`Database::bulk_put`]
+#[derive(Debug, Clone)]
+pub struct BulkOptions {
+    batch_bytes: usize,
+    batch_rows: usize,
+    parallelism: usize,
+    upsert: bool,
+    resume: Option<BulkCheckpoint>,
+}
+
+impl Default for BulkOptions {
+    fn default() -> Self {
+        BulkOptions {
+            batch_bytes: 1_000_000,
+            batch_rows: 10_000,
+            parallelism: 4,
+            upsert: false,
+            resume: None,
+        }
+    }
+}
+
+impl BulkOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    ///Approximate number of bytes written per transaction, FoundationDB rejects anything above 10MB
+    pub fn batch_bytes(mut self, bytes: usize) -> Self {
+        self.batch_bytes = bytes.max(1);
+        self
+    }
+    ///Maximum number of rows per transaction
+    pub fn batch_rows(mut self, rows: usize) -> Self {
+        self.batch_rows = rows.max(1);
+        self
+    }
+    ///Number of transactions that are committed at the same time
+    pub fn parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+    ///Read every row before writing it, so index entries of a previous version are removed
+    ///
+    /// By default rows are written blindly like [`crate::database::transaction::STransaction::put_value`]
+    pub fn upsert(mut self, upsert: bool) -> Self {
+        self.upsert = upsert;
+        self
+    }
+    ///Skip the rows a previous run already committed
+    ///
+    /// The input has to yield the same rows in the same order as in that run.
+    pub fn resume(mut self, checkpoint: BulkCheckpoint) -> Self {
+        self.resume = Some(checkpoint);
+        self
+    }
+}
+
+///Number of rows, counted from the start of the input, that are known to be committed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkCheckpoint {
+    pub rows: u64,
+}
+
+///Reported after every finished batch
+#[derive(Debug, Clone, Copy)]
+pub struct BulkProgress {
+    pub rows_written: u64,
+    pub bytes_written: u64,
+    pub batches_failed: usize,
+    ///Persist this to continue after a crash with [`BulkOptions::resume`]
+    pub checkpoint: BulkCheckpoint,
+}
+
+///A batch that could not be committed, rows are numbered from the start of the input
+#[derive(Debug)]
+pub struct FailedBatch {
+    pub first_row: u64,
+    pub rows: Vec<Uuid>,
+    pub error: ExothermError,
+}
+
+#[derive(Debug)]
+pub struct BulkReport {
+    pub rows_written: u64,
+    pub bytes_written: u64,
+    pub failed: Vec<FailedBatch>,
+    ///Stops before the first failed batch, so resuming retries it and everything after it
+    pub checkpoint: BulkCheckpoint,
+}
+
+struct Batch<T> {
+    first_row: u64,
+    rows: Vec<(Uuid, T, AlignedVec)>,
+    bytes: usize,
+    ///Set for a single row that could not be serialized, the batch then fails without a transaction
+    invalid: Option<ExothermError>,
+}
+
+///Tracks which batches finished, to advance the checkpoint only over a gapless prefix
+struct Completed {
+    checkpoint: u64,
+    done: Vec<(u64, u64)>,
+}
+
+impl Completed {
+    fn finish(&mut self, first_row: u64, rows: u64) {
+        self.done.push((first_row, rows));
+        self.done.sort_unstable();
+        while let Some(&(first, rows)) = self.done.first() {
+            if first != self.checkpoint {
+                break;
+            }
+            self.checkpoint += rows;
+            self.done.remove(0);
+        }
+    }
+}
+
+///Serialized row and the bytes it adds to a transaction, the row itself, one key per index and the data of new blobs
+fn estimate<T: RecordStruct>(record: &T, pk: Uuid) -> SResult<(AlignedVec, usize)> {
+    const KEY_OVERHEAD: usize = 64;
+    let corpus = record.serialize()?;
+    let row = corpus.len() + T::name().len() + KEY_OVERHEAD;
+    let indices = record.indices(pk).len() * (T::name().len() + KEY_OVERHEAD);
+    let blobs: usize = record
+        .blobs()
+        .iter()
+        .filter_map(|blob| blob.pending())
+        .map(<[u8]>::len)
+        .sum();
+    Ok((corpus, row + indices + blobs))
+}
+
+impl Database {
+    ///Write many rows, split into transactions that stay below a size limit
+    ///
+    /// Batches are committed concurrently, a failed batch does not stop the others.
+    /// A row that cannot be serialized is reported as a failed batch of its own.
+    /// `progress` is called after every batch, its checkpoint can be persisted to resume an import.
+    /// ```ignore
+    ///     let people = (0..1_000_000).map(|i| (Uuid::new_v4(), person(i)));
+    ///     let report = db
+    ///         .bulk_put(people, BulkOptions::new().parallelism(8), |progress| {
+    ///             println!("{} rows", progress.rows_written);
+    ///         })
+    ///         .await?;
+    ///     assert!(report.failed.is_empty());
+    /// ```
+    pub async fn bulk_put<T, I, P>(
+        &self,
+        rows: I,
+        options: BulkOptions,
+        mut progress: P,
+    ) -> SResult<BulkReport>
+    where
+        T: RecordStruct<Decoded = T>,
+        I: IntoIterator<Item = (Uuid, T)>,
+        P: FnMut(&BulkProgress),
+    {
+        let skip = options.resume.map(|c| c.rows).unwrap_or(0);
+        let mut rows = rows.into_iter().skip(skip as usize).map(|(pk, record)| {
+            let estimated = estimate(&record, pk);
+            (pk, record, estimated)
+        });
+        let mut next_row = skip;
+        //Row that did not fit into the previous batch, kept so it is not serialized again
+        let mut carry = None;
+        let batches = std::iter::from_fn(|| {
+            let mut batch = Batch {
+                first_row: next_row,
+                rows: Vec::new(),
+                bytes: 0,
+                invalid: None,
+            };
+            while let Some((pk, record, estimated)) = carry.take().or_else(|| rows.next()) {
+                let full = match &estimated {
+                    Ok((_, size)) => {
+                        batch.bytes + size > options.batch_bytes
+                            || batch.rows.len() >= options.batch_rows
+                    }
+                    Err(_) => true,
+                };
+                if full && !batch.rows.is_empty() {
+                    carry = Some((pk, record, estimated));
+                    break;
+                }
+                match estimated {
+                    Ok((corpus, size)) => {
+                        batch.bytes += size;
+                        batch.rows.push((pk, record, corpus));
+                    }
+                    Err(error) => {
+                        batch.rows.push((pk, record, AlignedVec::new()));
+                        batch.invalid = Some(error);
+                        break;
+                    }
+                }
+            }
+            next_row += batch.rows.len() as u64;
+            (!batch.rows.is_empty()).then_some(batch)
+        });
+
+        let upsert = options.upsert;
+        let mut results = stream::iter(batches)
+            .map(|mut batch| async move {
+                if let Some(error) = batch.invalid.take() {
+                    return (batch, Err(error));
+                }
+                let res = self
+                    .transact(|transaction| {
+                        let batch = &batch;
+                        async move {
+                            for (pk, record, corpus) in &batch.rows {
+                                if upsert {
+                                    transaction.upsert_encoded(record, *pk, corpus).await?;
+                                } else {
+                                    transaction.put_encoded(record, *pk, corpus).await?;
+                                }
+                            }
+                            Ok(())
+                        }
+                    })
+                    .await;
+                (batch, res)
+            })
+            .buffer_unordered(options.parallelism);
+
+        let mut completed = Completed {
+            checkpoint: skip,
+            done: Vec::new(),
+        };
+        let mut report = BulkReport {
+            rows_written: 0,
+            bytes_written: 0,
+            failed: Vec::new(),
+            checkpoint: BulkCheckpoint { rows: skip },
+        };
+        let mut first_failure: Option<u64> = None;
+        while let Some((batch, res)) = results.next().await {
+            match res {
+                Ok(()) => {
+                    report.rows_written += batch.rows.len() as u64;
+                    report.bytes_written += batch.bytes as u64;
+                    completed.finish(batch.first_row, batch.rows.len() as u64);
+                }
+                Err(error) => {
+                    first_failure = Some(match first_failure {
+                        Some(first) => first.min(batch.first_row),
+                        None => batch.first_row,
+                    });
+                    report.failed.push(FailedBatch {
+                        first_row: batch.first_row,
+                        rows: batch.rows.iter().map(|(pk, ..)| *pk).collect(),
+                        error,
+                    });
+                }
+            }
+            report.checkpoint = BulkCheckpoint {
+                rows: match first_failure {
+                    Some(first) => completed.checkpoint.min(first),
+                    None => completed.checkpoint,
+                },
+            };
+            progress(&BulkProgress {
+                rows_written: report.rows_written,
+                bytes_written: report.bytes_written,
+                batches_failed: report.failed.len(),
+                checkpoint: report.checkpoint,
+            });
+        }
+        report.failed.sort_by_key(|failed| failed.first_row);
+        Ok(report)
+    }
+}
@@ -0,0 +1,228 @@
+use foundationdb::{KeySelector, RangeOption, options::MutationType, options::StreamingMode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        database::Database,
+        key::{Key, KeyRange, Tenant},
+        transaction::STransaction,
+        view::ArchivedRecord,
+    },
+    error::SResult,
+};
+
+///Kind of write a change log entry records
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Put,
+    Clear,
+    ///Every row of the table was deleted at once, the table is empty if the whole tenant was cleared
+    Truncate,
+}
+
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug)]
+struct ChangeEntry {
+    table: String,
+    pk: Uuid,
+    op: ChangeOp,
+}
+
+///Position in the change log, persist it to continue tailing after a restart
+///
+/// Made of the commit versionstamp and the order of the write within its transaction.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct ChangeCursor([u8; 12]);
+
+impl ChangeCursor {
+    ///Before the first entry
+    pub fn start() -> Self {
+        Self::default()
+    }
+    pub fn as_bytes(&self) -> &[u8; 12] {
+        &self.0
+    }
+    pub fn from_bytes(bytes: [u8; 12]) -> Self {
+        ChangeCursor(bytes)
+    }
+}
+
+///A write recorded in the change log
+///
+/// Decode the corpora with [`crate::database::record::RecordStruct::decode`] or borrow them with [`ArchivedRecord::view`].
+pub struct Change {
+    pub cursor: ChangeCursor,
+    pub table: String,
+    pub pk: Uuid,
+    pub op: ChangeOp,
+    ///The row before the write, `None` if it did not exist and for truncates
+    pub old: Option<ArchivedRecord>,
+    ///The row after the write, `None` for clears
+    pub new: Option<ArchivedRecord>,
+}
+
+///Entries read per transaction
+const CHANGE_PAGE: usize = 1_000;
+///Each corpus of an entry is a key of its own, as both together can exceed the value size limit.
+/// The entry itself comes last, so an entry is complete once it was read.
+const OLD_PART: u8 = 0;
+const NEW_PART: u8 = 1;
+const ENTRY_PART: u8 = 2;
+
+impl STransaction {
+    ///Append a write to the change log, keyed by the commit versionstamp
+    pub(super) fn log_change(
+        &self,
+        table: &str,
+        pk: Uuid,
+        op: ChangeOp,
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> SResult<()> {
+        let entry = ChangeEntry {
+            table: table.to_string(),
+            pk,
+            op,
+        };
+        self.append_change(self.tenant.clone(), &entry, old, new)
+    }
+    ///Append a range delete of a table or, with an empty table name, of a whole tenant
+    ///
+    /// The deleted rows are not read, so the entry has a nil pk and no corpora.
+    pub(super) fn log_truncate(&self, tenant: Tenant, table: &str) -> SResult<()> {
+        let entry = ChangeEntry {
+            table: table.to_string(),
+            pk: Uuid::nil(),
+            op: ChangeOp::Truncate,
+        };
+        self.append_change(tenant, &entry, None, None)
+    }
+    fn append_change(
+        &self,
+        tenant: Tenant,
+        entry: &ChangeEntry,
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> SResult<()> {
+        let value = rkyv::to_bytes::<rkyv::rancor::Error>(entry)?;
+        let prefix = Key::change_log_prefix(tenant.clone())?;
+        let order = self.next_stamp_order()?;
+        for (part, corpus) in [(OLD_PART, old), (NEW_PART, new)] {
+            if let Some(corpus) = corpus {
+                self.set_versionstamped_at(prefix.clone(), order, &[part], corpus);
+            }
+        }
+        self.set_versionstamped_at(prefix, order, &[ENTRY_PART], &value);
+        let head = Key::new_changes(tenant, "").generate()?;
+        self.trx
+            .atomic_op(&head, &1u64.to_le_bytes(), MutationType::Add);
+        Ok(())
+    }
+}
+
+impl Database {
+    ///Read up to `limit` change log entries after `cursor`, in commit order
+    ///
+    /// Only writes made with [`crate::database::options::TransactOptions::capture_changes`] are logged,
+    /// enable it in the database defaults to log every put, clear, truncate and bulk write.
+    pub async fn read_changes(&self, cursor: ChangeCursor, limit: usize) -> SResult<Vec<Change>> {
+        let prefix = Key::change_log_prefix(self.tenant())?;
+        let prefix_len = prefix.len();
+        let KeyRange { begin: _, end } = KeyRange::prefix(prefix.clone());
+        //Past every part of the entry at `cursor`
+        let mut begin = prefix;
+        begin.extend_from_slice(cursor.as_bytes());
+        begin.push(0xff);
+        let limit = limit.min(CHANGE_PAGE);
+        self.transact(|transaction| {
+            let (begin, end) = (&begin, &end);
+            async move {
+                let mut opt = RangeOption {
+                    begin: KeySelector::first_greater_or_equal(begin.as_slice()),
+                    end: KeySelector::first_greater_or_equal(end.as_slice()),
+                    mode: StreamingMode::WantAll,
+                    ..RangeOption::default()
+                };
+                let mut changes = Vec::new();
+                let (mut old, mut new) = (None, None);
+                let mut iteration = 1;
+                loop {
+                    let values = transaction.trx.get_range(&opt, iteration, true).await?;
+                    for kv in &values {
+                        let key = &kv.key()[prefix_len..];
+                        match key.get(12) {
+                            Some(&OLD_PART) => old = Some(ArchivedRecord::new(kv.value())),
+                            Some(&NEW_PART) => new = Some(ArchivedRecord::new(kv.value())),
+                            _ => {
+                                let mut cursor = [0u8; 12];
+                                cursor.copy_from_slice(&key[..12]);
+                                let record = ArchivedRecord::new(kv.value());
+                                let entry = rkyv::from_bytes::<ChangeEntry, rkyv::rancor::Error>(
+                                    record.bytes(),
+                                )?;
+                                changes.push(Change {
+                                    cursor: ChangeCursor(cursor),
+                                    table: entry.table,
+                                    pk: entry.pk,
+                                    op: entry.op,
+                                    old: old.take(),
+                                    new: new.take(),
+                                });
+                                if changes.len() == limit {
+                                    return Ok(changes);
+                                }
+                            }
+                        }
+                    }
+                    match opt.next_range(&values) {
+                        Some(next) => opt = next,
+                        None => return Ok(changes),
+                    }
+                    iteration += 1;
+                }
+            }
+        })
+        .await
+    }
+    ///Wait for entries after `cursor`, returns at once if there already are some
+    /// ```ignore
+    ///     let mut cursor = load_cursor()?;
+    ///     loop {
+    ///         for change in db.tail_changes(cursor).await? {
+    ///             index_for_search(&change)?;
+    ///             cursor = change.cursor;
+    ///         }
+    ///         save_cursor(cursor)?;
+    ///     }
+    /// ```
+    pub async fn tail_changes(&self, cursor: ChangeCursor) -> SResult<Vec<Change>> {
+        loop {
+            let changed = self
+                .transact(|transaction| async move {
+                    let head = Key::new_changes(transaction.tenant.clone(), "").generate()?;
+                    Ok(transaction.trx.watch(&head))
+                })
+                .await?;
+            let changes = self.read_changes(cursor, CHANGE_PAGE).await?;
+            if !changes.is_empty() {
+                return Ok(changes);
+            }
+            changed.await?;
+        }
+    }
+    ///Delete the entries up to and including `cursor`, once every consumer is past it
+    pub async fn trim_changes(&self, cursor: ChangeCursor) -> SResult<()> {
+        self.transact(|transaction| async move {
+            let begin = Key::change_log_prefix(self.tenant())?;
+            let mut end = begin.clone();
+            end.extend_from_slice(cursor.as_bytes());
+            end.push(0xff);
+            transaction.writable()?;
+            transaction.trx.clear_range(&begin, &end);
+            Ok(())
+        })
+        .await
+    }
+}
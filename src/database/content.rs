@@ -0,0 +1,132 @@
+use std::fmt;
+
+use foundationdb::options::MutationType;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        blobstore::{BlobOptions, decode_count},
+        key::Key,
+        transaction::STransaction,
+    },
+    error::{ExothermError, SResult},
+};
+
+///SHA-256 of deduplicated content, store it in the records that reference the content
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, rkyv::Serialize, rkyv::Archive, rkyv::Deserialize,
+)]
+pub struct ContentId(pub [u8; 32]);
+
+impl ContentId {
+    pub fn of(data: &[u8]) -> Self {
+        ContentId(Sha256::digest(data).into())
+    }
+    ///Id the content is stored under, the first 128 bits of the hash
+    pub fn id(&self) -> Uuid {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&self.0[..16]);
+        Uuid::from_bytes(bytes)
+    }
+}
+
+impl fmt::Display for ContentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl STransaction {
+    ///Store content unless it already exists and add a reference to it
+    ///
+    /// Content is stored once per bucket, apart from blobs stored by id, and deleted when its last
+    /// reference is released. Change references in the transaction that writes or clears the
+    /// referencing record, so the counts stay in sync with the records:
+    /// ```ignore
+    ///     db.transact(|transaction| {
+    ///         let (message, attachment) = (&message, &attachment);
+    ///         async move {
+    ///             let content = transaction.put_content("attachments", attachment).await?;
+    ///             transaction.put_value(&Message { content, ..message.clone() }, id).await?;
+    ///             if let Some(old) = previous {
+    ///                 transaction.release_content("attachments", &old).await?;
+    ///             }
+    ///             Ok(())
+    ///         }
+    ///     })
+    ///     .await?;
+    /// ```
+    /// [`crate::database::blobstore::Blob::deduplicated`] columns do this when the row is put or cleared.
+    pub async fn put_content(&self, bucket: &str, data: &[u8]) -> SResult<ContentId> {
+        self.put_content_with_options(bucket, data, &BlobOptions::default())
+            .await
+    }
+    ///Store content with a content type or compression, which are ignored if the content already exists
+    pub async fn put_content_with_options(
+        &self,
+        bucket: &str,
+        data: &[u8],
+        options: &BlobOptions,
+    ) -> SResult<ContentId> {
+        let content = ContentId::of(data);
+        let key = Key::new_content(self.tenant.clone(), bucket, content.id()).generate()?;
+        match self.read_manifest(&key).await? {
+            Some(manifest) if manifest.sha256 == content.0 => (),
+            //Only the first 128 bits of the hash are in the key
+            Some(_) => {
+                return Err(ExothermError::ContentCollision {
+                    bucket: bucket.to_string(),
+                    content: content.to_string(),
+                });
+            }
+            None => {
+                self.store_blob(bucket, &key, data, options).await?;
+            }
+        }
+        self.retain_content(bucket, &content)?;
+        Ok(content)
+    }
+    ///Add a reference to content that already exists, e.g. when a record referencing it is copied
+    pub fn retain_content(&self, bucket: &str, content: &ContentId) -> SResult<()> {
+        self.writable()?;
+        let key = Key::new_content_refs(self.tenant.clone(), bucket, content.id()).generate()?;
+        self.trx
+            .atomic_op(&key, &1u64.to_le_bytes(), MutationType::Add);
+        Ok(())
+    }
+    ///Remove a reference, returns whether it was the last one and the content was deleted
+    ///
+    /// Returns `false` without deleting anything if the content has no references.
+    /// The count is read, so this conflicts with transactions that add references at the same time.
+    pub async fn release_content(&self, bucket: &str, content: &ContentId) -> SResult<bool> {
+        self.writable()?;
+        let key = Key::new_content_refs(self.tenant.clone(), bucket, content.id()).generate()?;
+        match self.content_refs(bucket, content).await? {
+            0 => return Ok(false),
+            1 => (),
+            _ => {
+                self.trx
+                    .atomic_op(&key, &u64::MAX.to_le_bytes(), MutationType::Add);
+                return Ok(false);
+            }
+        }
+        self.trx.clear(&key);
+        let manifest = Key::new_content(self.tenant.clone(), bucket, content.id()).generate()?;
+        self.remove_blob(bucket, &manifest).await?;
+        Ok(true)
+    }
+    pub async fn get_content(&self, bucket: &str, content: &ContentId) -> SResult<Option<Vec<u8>>> {
+        let key = Key::new_content(self.tenant.clone(), bucket, content.id()).generate()?;
+        self.load_blob(bucket, content.id(), &key).await
+    }
+    ///Number of references to content, 0 if it does not exist
+    pub async fn content_refs(&self, bucket: &str, content: &ContentId) -> SResult<u64> {
+        let key = Key::new_content_refs(self.tenant.clone(), bucket, content.id()).generate()?;
+        let value = self.trx.get(&key, false).await?;
+        Ok(value.as_deref().map(decode_count).unwrap_or_default())
+    }
+}
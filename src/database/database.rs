@@ -1,15 +1,27 @@
-use foundationdb::{FdbBindingError, api::NetworkAutoStop};
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[cfg(feature = "native-tenants")]
+use foundationdb::tenant::TenantManagement;
+use foundationdb::{FdbBindingError, FdbError, RetryableTransaction, api::NetworkAutoStop};
 //use uuid::Uuid;
 
 use crate::{
-    database::transaction::STransaction,
+    database::{options::TransactOptions, record::RecordStruct, transaction::STransaction},
     error::{ExothermError, SResult},
 };
 
 pub struct Database {
     _autodrop: Option<NetworkAutoStop>,
     tenant: Tenant,
-    fdb: foundationdb::Database,
+    pub(super) fdb: foundationdb::Database,
+    tables: HashMap<&'static str, (TypeId, &'static str)>,
+    options: TransactOptions,
+    #[cfg(feature = "native-tenants")]
+    pub(super) native_tenants: bool,
 }
 
 /*pub struct Page {
@@ -72,9 +84,66 @@ impl Database {
             },
             tenant,
             fdb: foundationdb::Database::default()?,
+            tables: HashMap::new(),
+            options: TransactOptions::default(),
+            #[cfg(feature = "native-tenants")]
+            native_tenants: false,
         };
         Ok(db)
     }
+    ///Register a table on this database
+    ///
+    /// Fails if a different type already registered the same table name, as both would read and write the same rows
+    pub fn register<T: RecordStruct + 'static>(&mut self) -> SResult<()> {
+        let table = T::name();
+        let type_name = std::any::type_name::<T>();
+        match self.tables.get(table) {
+            Some((id, _)) if *id == TypeId::of::<T>() => Ok(()),
+            Some((_, existing)) => Err(ExothermError::DuplicateTable {
+                table,
+                existing,
+                new: type_name,
+            }),
+            None => {
+                self.tables.insert(table, (TypeId::of::<T>(), type_name));
+                Ok(())
+            }
+        }
+    }
+    ///Tenant transactions use unless started with `transact_with_tenant`
+    pub fn tenant(&self) -> Tenant {
+        self.tenant.clone()
+    }
+    ///Options every transaction of this database starts with
+    pub fn options(&self) -> &TransactOptions {
+        &self.options
+    }
+    ///Replace the default options, e.g. with ones loaded by [`TransactOptions::from_toml`]
+    pub fn set_options(&mut self, options: TransactOptions) {
+        self.options = options;
+    }
+    ///Run transactions inside the FoundationDB tenant named like the exotherm tenant
+    ///
+    /// The cluster then enforces the isolation between tenants instead of only the key prefixes.
+    /// Keys keep their tenant prefix, so data written before switching stays readable after moving it into
+    /// the native tenant. The cluster needs `tenant_mode` enabled and the tenant created with
+    /// [`Self::create_native_tenant`], otherwise transactions fail with `tenant_not_found`.
+    #[cfg(feature = "native-tenants")]
+    pub fn set_native_tenants(&mut self, enabled: bool) {
+        self.native_tenants = enabled;
+    }
+    ///Create the FoundationDB tenant that transactions of `tenant` run in with native tenants
+    #[cfg(feature = "native-tenants")]
+    pub async fn create_native_tenant(&self, tenant: &Tenant) -> SResult<()> {
+        TenantManagement::create_tenant(&self.fdb, tenant.as_bytes()?).await?;
+        Ok(())
+    }
+    ///Delete an empty FoundationDB tenant, clear its data first with [`Self::drop_tenant`]
+    #[cfg(feature = "native-tenants")]
+    pub async fn delete_native_tenant(&self, tenant: &Tenant) -> SResult<()> {
+        TenantManagement::delete_tenant(&self.fdb, tenant.as_bytes()?).await?;
+        Ok(())
+    }
     ///Start a transaction
     ///
     /// ```ignore
@@ -95,44 +164,167 @@ impl Database {
     ///     .await.unwrap();
     ///
     /// ```
-    pub async fn transact<F, Fut, T>(&self, closure: F) -> Result<T, ExothermError>
+    pub async fn transact<F, Fut, T>(&self, closure: F) -> SResult<T>
     where
         F: Fn(STransaction) -> Fut,
-        Fut: Future<Output = Result<T, FdbBindingError>>,
+        Fut: Future<Output = SResult<T>>,
     {
-        let result = self
-            .fdb
-            .run(|trx, maybe_committed| async {
-                let st = STransaction {
-                    trx,
-                    maybe_commited: maybe_committed.into(),
-                    tenant: self.tenant,
-                };
-                closure(st).await
-            })
-            .await?;
-        Ok(result)
+        self.run(self.tenant.clone(), &self.options, closure).await
     }
-    pub async fn transact_with_tenant<F, Fut, T>(
+    ///Start a transaction with options other than the database defaults
+    ///
+    /// ```ignore
+    ///     let options = db.options().clone().timeout(Duration::from_millis(500)).read_only(true);
+    ///     db.transact_with_options(&options, |transaction| async move {
+    ///         transaction.get_value::<Person>(id).await
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn transact_with_options<F, Fut, T>(
+        &self,
+        options: &TransactOptions,
+        closure: F,
+    ) -> SResult<T>
+    where
+        F: Fn(STransaction) -> Fut,
+        Fut: Future<Output = SResult<T>>,
+    {
+        self.run(self.tenant.clone(), options, closure).await
+    }
+    pub async fn transact_with_tenant<F, Fut, T>(&self, tenant: Tenant, closure: F) -> SResult<T>
+    where
+        F: Fn(STransaction) -> Fut,
+        Fut: Future<Output = SResult<T>>,
+    {
+        self.run(tenant, &self.options, closure).await
+    }
+    ///Start a transaction whose closure returns an application error type
+    ///
+    /// Errors that carry a retryable FoundationDB error are retried, every other error
+    /// aborts the transaction and is returned unchanged.
+    /// ```ignore
+    ///     #[derive(Debug, thiserror::Error)]
+    ///     enum AppError {
+    ///         #[error(transparent)]
+    ///         Db(#[from] ExothermError),
+    ///         #[error("out of stock")]
+    ///         OutOfStock,
+    ///     }
+    ///     impl TransactionError for AppError {
+    ///         fn fdb_error(&self) -> Option<FdbError> {
+    ///             match self {
+    ///                 AppError::Db(e) => e.fdb_error(),
+    ///                 _ => None,
+    ///             }
+    ///         }
+    ///     }
+    ///     let res: Result<(), AppError> = db.transact_typed(|transaction| async move {
+    ///         Err(AppError::OutOfStock)
+    ///     }).await;
+    /// ```
+    pub async fn transact_typed<E, F, Fut, T>(&self, closure: F) -> Result<T, E>
+    where
+        E: TransactionError,
+        F: Fn(STransaction) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.run(self.tenant.clone(), &self.options, closure).await
+    }
+    pub async fn transact_typed_with_tenant<E, F, Fut, T>(
         &self,
         tenant: Tenant,
         closure: F,
-    ) -> Result<T, ExothermError>
+    ) -> Result<T, E>
     where
+        E: TransactionError,
         F: Fn(STransaction) -> Fut,
-        Fut: Future<Output = Result<T, FdbBindingError>>,
+        Fut: Future<Output = Result<T, E>>,
     {
+        self.run(tenant, &self.options, closure).await
+    }
+    async fn run<E, F, Fut, T>(
+        &self,
+        tenant: Tenant,
+        options: &TransactOptions,
+        closure: F,
+    ) -> Result<T, E>
+    where
+        E: TransactionError,
+        F: Fn(STransaction) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let (tenant, closure) = (&tenant, &closure);
+        let first_attempt = &AtomicBool::new(true);
+        let attempt = |trx: RetryableTransaction, maybe_committed: bool| async move {
+            options.apply(&trx, first_attempt.swap(false, Ordering::Relaxed))?;
+            let st = STransaction {
+                trx,
+                maybe_commited: maybe_committed,
+                tenant: tenant.clone(),
+                read_only: options.is_read_only(),
+                capture_changes: options.is_capturing_changes(),
+                notify_subscribers: options.is_notifying_subscribers(),
+                stamp_order: Default::default(),
+            };
+            closure(st).await.map_err(|e| match e.fdb_error() {
+                //Only a bare FdbError is checked for retries by the binding
+                Some(fdb) => FdbBindingError::from(fdb),
+                None => FdbBindingError::new_custom_error(Box::new(e)),
+            })
+        };
+        #[cfg(feature = "native-tenants")]
+        let result = if self.native_tenants {
+            let native = self
+                .fdb
+                .open_tenant(tenant.as_bytes()?)
+                .map_err(ExothermError::from)?;
+            native.run(attempt).await
+        } else {
+            self.fdb
+                .run(|trx, maybe_committed| attempt(trx, maybe_committed.into()))
+                .await
+        };
+        #[cfg(not(feature = "native-tenants"))]
         let result = self
             .fdb
-            .run(|trx, maybe_committed| async {
-                let st = STransaction {
-                    trx,
-                    maybe_commited: maybe_committed.into(),
-                    tenant,
-                };
-                closure(st).await
-            })
-            .await?;
-        Ok(result)
+            .run(|trx, maybe_committed| attempt(trx, maybe_committed.into()))
+            .await;
+        result.map_err(|e| match e {
+            FdbBindingError::CustomError(custom) => match custom.downcast::<E>() {
+                Ok(e) => *e,
+                Err(custom) => E::from(ExothermError::FoundationDBBinding(
+                    FdbBindingError::CustomError(custom),
+                )),
+            },
+            FdbBindingError::NonRetryableFdbError(fdb) => E::from(ExothermError::FoundationDB(fdb)),
+            e => E::from(ExothermError::FoundationDBBinding(e)),
+        })
+    }
+}
+
+///Wall clock time for leases and expiries, which have to tolerate clock skew between clients
+pub(crate) fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+///Error type that can be returned from a transaction closure
+///
+/// [`TransactionError::fdb_error`] tells the retry loop which errors came from FoundationDB.
+pub trait TransactionError:
+    From<ExothermError> + std::error::Error + Send + Sync + 'static
+{
+    fn fdb_error(&self) -> Option<FdbError>;
+}
+
+impl TransactionError for ExothermError {
+    fn fdb_error(&self) -> Option<FdbError> {
+        match self {
+            ExothermError::FoundationDB(e) => Some(*e),
+            ExothermError::FoundationDBBinding(e) => e.get_fdb_error(),
+            _ => None,
+        }
     }
 }
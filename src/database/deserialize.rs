@@ -1,6 +1,9 @@
 use uuid::Uuid;
 
-use crate::{database::values_indices::DbValue, error::ConvertError};
+use crate::{
+    database::{blobstore::Blob, values_indices::DbValue},
+    error::ConvertError,
+};
 
 impl TryFrom<DbValue> for String {
     type Error = ConvertError;
@@ -24,6 +27,33 @@ impl TryFrom<DbValue> for Vec<u8> {
     }
 }
 
+impl TryFrom<DbValue> for Blob {
+    type Error = ConvertError;
+    fn try_from(value: DbValue) -> Result<Self, Self::Error> {
+        match value {
+            DbValue::BlobAddress(address) => Ok(Blob::stored(address)),
+            DbValue::ContentAddress(address, content) => {
+                Ok(Blob::stored_deduplicated(address, content))
+            }
+            _ => Err(ConvertError::CantConvert { from: value }),
+        }
+    }
+}
+
+impl TryFrom<DbValue> for Option<Blob> {
+    type Error = ConvertError;
+    fn try_from(value: DbValue) -> Result<Self, Self::Error> {
+        match value {
+            DbValue::BlobAddress(address) => Ok(Some(Blob::stored(address))),
+            DbValue::ContentAddress(address, content) => {
+                Ok(Some(Blob::stored_deduplicated(address, content)))
+            }
+            DbValue::None => Ok(None),
+            _ => Err(ConvertError::CantConvert { from: value }),
+        }
+    }
+}
+
 impl TryFrom<DbValue> for Vec<f32> {
     type Error = ConvertError;
     fn try_from(value: DbValue) -> Result<Self, Self::Error> {
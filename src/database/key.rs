@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use uuid::Uuid;
 
 use crate::error::SResult;
@@ -5,6 +7,8 @@ use crate::error::SResult;
 use super::values_indices::IndexableValue;
 
 static MAGIC_NUMBER: u8 = 99;
+///First byte of the tenant catalog, which is outside of every tenant
+const CATALOG_NUMBER: u8 = 98;
 
 pub struct Key {
     pub(super) tenant: Tenant,
@@ -13,31 +17,92 @@ pub struct Key {
     pub(super) row: Uuid,
 }
 
-#[derive(Debug, Clone, Copy)]
+///Tenant whose keys are prefixed with its name or id
+///
+/// Names known at runtime, e.g. loaded from a catalog, use [`Tenant::named`];
+/// `Named` and `Shared` with the same name address the same keys.
+#[derive(Debug, Clone)]
 pub enum Tenant {
     Named(&'static str),
+    Shared(Arc<str>),
     Id(Uuid),
     Unset,
 }
 
 impl Tenant {
+    ///Tenant with a name that is not known at compile time
+    pub fn named(name: impl Into<Arc<str>>) -> Self {
+        Tenant::Shared(name.into())
+    }
+    ///Bytes identifying the tenant, its key prefix and the name of its native FoundationDB tenant
+    ///
+    /// Fails for empty names and names with NUL bytes, whose keys would overlap with other tenants.
+    pub fn as_bytes(&self) -> SResult<&[u8]> {
+        match self {
+            Tenant::Named(name) => Self::valid_name(name),
+            Tenant::Shared(name) => Self::valid_name(name),
+            Tenant::Id(uuid) => Ok(uuid.as_bytes()),
+            Tenant::Unset => Err(crate::error::ExothermError::TenantError),
+        }
+    }
+    fn valid_name(name: &str) -> SResult<&[u8]> {
+        if name.is_empty() || name.contains('\0') {
+            return Err(crate::error::ExothermError::InvalidTenant(name.to_string()));
+        }
+        Ok(name.as_bytes())
+    }
     fn append(&self, key: &mut Vec<u8>) -> SResult<()> {
+        key.extend_from_slice(self.as_bytes()?);
+        Ok(())
+    }
+    ///Byte that tells names and ids apart, so an id never falls into the keys of a name
+    fn kind(&self) -> SResult<u8> {
         match self {
-            Tenant::Named(name) => {
-                for b in name.as_bytes() {
-                    key.push(*b);
-                }
-                Ok(())
-            }
-            Tenant::Id(uuid) => {
-                for b in uuid.as_bytes() {
-                    key.push(*b);
-                }
-                Ok(())
-            }
+            Tenant::Named(_) | Tenant::Shared(_) => Ok(Self::NAME_KIND),
+            Tenant::Id(_) => Ok(Self::ID_KIND),
             Tenant::Unset => Err(crate::error::ExothermError::TenantError),
         }
     }
+    ///Key of the tenant in the catalog, see [`crate::database::tenants`]
+    pub fn catalog_key(&self) -> SResult<Vec<u8>> {
+        let mut key = vec![CATALOG_NUMBER, self.kind()?];
+        self.append(&mut key)?;
+        Ok(key)
+    }
+    ///Tenant a catalog key belongs to, names come back as [`Tenant::Shared`]
+    pub fn from_catalog_key(key: &[u8]) -> SResult<Self> {
+        match key {
+            [CATALOG_NUMBER, Self::NAME_KIND, name @ ..] => std::str::from_utf8(name)
+                .map(Tenant::named)
+                .map_err(|_| crate::error::ExothermError::TenantError),
+            [CATALOG_NUMBER, Self::ID_KIND, id @ ..] => Ok(Tenant::Id(Uuid::from_slice(id)?)),
+            _ => Err(crate::error::ExothermError::TenantError),
+        }
+    }
+    ///Range covering the catalog entries of every tenant
+    pub fn catalog_range() -> KeyRange {
+        KeyRange::prefix(vec![CATALOG_NUMBER])
+    }
+    const NAME_KIND: u8 = 1;
+    const ID_KIND: u8 = 2;
+}
+
+impl From<&'static str> for Tenant {
+    fn from(name: &'static str) -> Self {
+        Tenant::Named(name)
+    }
+}
+
+impl From<String> for Tenant {
+    fn from(name: String) -> Self {
+        Tenant::Shared(name.into())
+    }
+}
+
+impl From<Uuid> for Tenant {
+    fn from(id: Uuid) -> Self {
+        Tenant::Id(id)
+    }
 }
 
 impl Key {
@@ -63,57 +128,251 @@ impl Key {
             row,
         }
     }
+    pub fn new_version(tenant: Tenant, table: &'static str, row: Uuid) -> Self {
+        Key {
+            tenant,
+            table,
+            purpose: Purpose::Version,
+            row,
+        }
+    }
+    ///Key of one shard of a blob, blobs belong to the tenant and not to a table
+    pub fn new_blob(tenant: Tenant, bucket: impl Into<String>, id: Uuid, shard: u16) -> Self {
+        Key {
+            tenant,
+            table: "",
+            purpose: Purpose::Blob(bucket.into(), shard),
+            row: id,
+        }
+    }
+    ///Key of the manifest that tells where the data of a blob is stored
+    pub fn new_blob_manifest(tenant: Tenant, bucket: impl Into<String>, id: Uuid) -> Self {
+        Key {
+            tenant,
+            table: "",
+            purpose: Purpose::BlobManifest(bucket.into()),
+            row: id,
+        }
+    }
+    ///Number of rows that reference a blob in a [`crate::database::blobstore::Blob`] column
+    pub fn new_blob_refs(tenant: Tenant, bucket: impl Into<String>, id: Uuid) -> Self {
+        Key {
+            tenant,
+            table: "",
+            purpose: Purpose::BlobRefs(bucket.into()),
+            row: id,
+        }
+    }
+    ///Manifest of deduplicated content, `id` is [`crate::database::content::ContentId::id`]
+    pub fn new_content(tenant: Tenant, bucket: impl Into<String>, id: Uuid) -> Self {
+        Key {
+            tenant,
+            table: "",
+            purpose: Purpose::Content(bucket.into()),
+            row: id,
+        }
+    }
+    ///Number of references to deduplicated content, see [`crate::database::content`]
+    pub fn new_content_refs(tenant: Tenant, bucket: impl Into<String>, id: Uuid) -> Self {
+        Key {
+            tenant,
+            table: "",
+            purpose: Purpose::ContentRefs(bucket.into()),
+            row: id,
+        }
+    }
+    ///Marker of an upload that has not been published yet, `data` is the id its shards are stored under
+    pub fn new_blob_pending(tenant: Tenant, data: Uuid) -> Self {
+        Key {
+            tenant,
+            table: "",
+            purpose: Purpose::BlobPending,
+            row: data,
+        }
+    }
+    ///Key remembering the outcome of an idempotent transaction, see [`crate::database::transaction::STransaction::idempotent`]
+    pub fn new_idempotency(tenant: Tenant, id: Uuid) -> Self {
+        Key {
+            tenant,
+            table: "",
+            purpose: Purpose::Idempotency,
+            row: id,
+        }
+    }
+    ///Counter of all writes to a table, watched by index subscriptions
+    pub fn new_changes(tenant: Tenant, table: &'static str) -> Self {
+        Key {
+            tenant,
+            table,
+            purpose: Purpose::Changes,
+            row: Uuid::nil(),
+        }
+    }
     pub fn generate(&self) -> SResult<Vec<u8>> {
+        if let Purpose::Blob(bucket, _)
+        | Purpose::BlobManifest(bucket)
+        | Purpose::BlobRefs(bucket)
+        | Purpose::Content(bucket)
+        | Purpose::ContentRefs(bucket) = &self.purpose
+            && (bucket.is_empty() || bucket.contains('\0'))
+        {
+            return Err(crate::error::ExothermError::InvalidBucket(bucket.clone()));
+        }
+        let mut key = Self::table_prefix(self.tenant.clone(), self.table)?;
+        self.purpose.append(&mut key);
+        key.push(0);
+        for b in self.row.as_bytes() {
+            key.push(*b);
+        }
+        if let Purpose::Blob(_, shard) = &self.purpose {
+            key.extend_from_slice(&shard.to_be_bytes());
+        }
+
+        Ok(key)
+    }
+    ///Range covering every shard of a blob
+    pub fn blob_range(tenant: Tenant, bucket: &str, id: Uuid) -> SResult<KeyRange> {
+        let mut begin = Key::new_blob(tenant, bucket, id, 0).generate()?;
+        begin.truncate(begin.len() - 2);
+        let mut end = begin.clone();
+        end.extend_from_slice(&[0xff, 0xff, 0xff]);
+        Ok(KeyRange { begin, end })
+    }
+    ///Prefix shared by every key of a tenant
+    pub fn tenant_prefix(tenant: Tenant) -> SResult<Vec<u8>> {
         //assert_ne!(self.tenant, "invalid");
         let mut key = Vec::<u8>::with_capacity(128);
         key.push(MAGIC_NUMBER);
-        self.tenant.append(&mut key)?;
+        key.push(tenant.kind()?);
+        tenant.append(&mut key)?;
         /*for b in self.tenant.as_bytes() {
             key.push(*b);
         }*/
         key.push(0);
-        for b in self.table.as_bytes() {
+        Ok(key)
+    }
+    ///Prefix shared by every key of a table
+    pub fn table_prefix(tenant: Tenant, table: &str) -> SResult<Vec<u8>> {
+        let mut key = Self::tenant_prefix(tenant)?;
+        for b in table.as_bytes() {
             key.push(*b);
         }
         key.push(0);
-        self.purpose.append(&mut key);
-        key.push(0);
-        for b in self.row.as_bytes() {
-            key.push(*b);
-        }
-
         Ok(key)
     }
+    ///Prefix of the change log entries of a tenant, followed by a versionstamp
+    pub fn change_log_prefix(tenant: Tenant) -> SResult<Vec<u8>> {
+        let mut key = Self::table_prefix(tenant, "")?;
+        key.push(Purpose::CHANGE_LOG_TAG);
+        Ok(key)
+    }
+    ///Prefix of the outbox events of a tenant, followed by a versionstamp
+    pub fn outbox_prefix(tenant: Tenant) -> SResult<Vec<u8>> {
+        let mut key = Self::table_prefix(tenant, "")?;
+        key.push(Purpose::OUTBOX_TAG);
+        Ok(key)
+    }
+    ///Lease and head counter of the outbox of a tenant
+    pub fn outbox_meta(tenant: Tenant, which: u8) -> SResult<Vec<u8>> {
+        let mut key = Self::table_prefix(tenant, "")?;
+        key.push(Purpose::OUTBOX_META_TAG);
+        key.push(which);
+        Ok(key)
+    }
+    ///Range covering every key of a table with one of the `Purpose::*_TAG` bytes
+    pub fn purpose_range(tenant: Tenant, table: &str, tag: u8) -> SResult<KeyRange> {
+        let mut begin = Self::table_prefix(tenant, table)?;
+        begin.push(tag);
+        Ok(KeyRange::prefix(begin))
+    }
+}
+
+///Keys from `begin` up to, but not including, `end`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange {
+    pub begin: Vec<u8>,
+    pub end: Vec<u8>,
+}
+
+impl KeyRange {
+    ///Every key starting with `prefix`, which must not end in 0xff
+    pub fn prefix(prefix: Vec<u8>) -> Self {
+        let mut end = prefix.clone();
+        if let Some(last) = end.last_mut() {
+            *last += 1;
+        }
+        KeyRange { begin: prefix, end }
+    }
 }
 
 pub enum Purpose {
     Row,                        //Stores the row corpus
     Index(u16, IndexableValue), //Stores the index,
-    Blob(&'static str, u16),    //Stores the blob bucket
+    Blob(String, u16),          //Stores a shard of a blob in a bucket
+    BlobManifest(String),       //Stores which data a blob in a bucket points to
+    BlobPending,                //Marks blob data of an unfinished upload
+    BlobRefs(String),           //Stores the number of rows referencing a blob
+    Content(String),            //Stores the manifest of deduplicated content
+    ContentRefs(String),        //Stores the reference count of deduplicated content
+    Version,                    //Stores the write counter of a row
+    Idempotency,                //Stores the outcome of a committed transaction
+    Changes,                    //Stores the write counter of a table
 }
 
 impl Purpose {
-    fn append(&self, key: &mut Vec<u8>) {
+    pub const ROW_TAG: u8 = 1;
+    pub const INDEX_TAG: u8 = 2;
+    pub const BLOB_TAG: u8 = 3;
+    pub const VERSION_TAG: u8 = 4;
+    pub const IDEMPOTENCY_TAG: u8 = 5;
+    pub const CHANGES_TAG: u8 = 6;
+    ///Change log entries have no [`Purpose`] value as they are not addressed by row
+    pub const CHANGE_LOG_TAG: u8 = 7;
+    pub const OUTBOX_TAG: u8 = 8;
+    pub const OUTBOX_META_TAG: u8 = 9;
+    pub const BLOB_MANIFEST_TAG: u8 = 10;
+    pub const BLOB_PENDING_TAG: u8 = 11;
+    pub const BLOB_REFS_TAG: u8 = 12;
+    pub const CONTENT_TAG: u8 = 13;
+    pub const CONTENT_REFS_TAG: u8 = 14;
+    fn tag(&self) -> u8 {
         match self {
-            Purpose::Row => key.push(1),
-            Purpose::Index(_, _indexable_value) => key.push(2),
-            Purpose::Blob(_, _) => key.push(3),
+            Purpose::Row => Self::ROW_TAG,
+            Purpose::Index(_, _indexable_value) => Self::INDEX_TAG,
+            Purpose::Blob(_, _) => Self::BLOB_TAG,
+            Purpose::Version => Self::VERSION_TAG,
+            Purpose::Idempotency => Self::IDEMPOTENCY_TAG,
+            Purpose::Changes => Self::CHANGES_TAG,
+            Purpose::BlobManifest(_) => Self::BLOB_MANIFEST_TAG,
+            Purpose::BlobPending => Self::BLOB_PENDING_TAG,
+            Purpose::BlobRefs(_) => Self::BLOB_REFS_TAG,
+            Purpose::Content(_) => Self::CONTENT_TAG,
+            Purpose::ContentRefs(_) => Self::CONTENT_REFS_TAG,
         }
+    }
+    fn append(&self, key: &mut Vec<u8>) {
+        key.push(self.tag());
         match self {
-            Purpose::Row => (),
+            Purpose::Row
+            | Purpose::Version
+            | Purpose::Idempotency
+            | Purpose::Changes
+            | Purpose::BlobPending => (),
             Purpose::Index(index_col, indexable_value) => {
                 let [b1, b2] = index_col.to_be_bytes();
                 key.push(b1);
                 key.push(b2);
                 indexable_value.append_to_key(key);
             }
-            Purpose::Blob(bucket, shard) => {
+            //The shard is appended after the row, so that the shards of a blob are next to each other
+            Purpose::Blob(bucket, _)
+            | Purpose::BlobManifest(bucket)
+            | Purpose::BlobRefs(bucket)
+            | Purpose::Content(bucket)
+            | Purpose::ContentRefs(bucket) => {
                 for b in bucket.as_bytes() {
                     key.push(*b);
                 }
-                let [b1, b2] = shard.to_be_bytes();
-                key.push(b1);
-                key.push(b2);
             }
         }
     }
@@ -1,10 +1,21 @@
 pub mod blobstore;
+pub mod blobstream;
+pub mod bulk;
+pub mod changes;
+pub mod content;
+#[allow(clippy::module_inception)]
 pub mod database;
 pub mod deserialize;
 pub mod error;
 //pub mod index_repr;
 pub mod key;
+pub mod options;
+pub mod outbox;
 pub mod record;
 pub mod row;
+pub mod tenants;
 pub mod transaction;
+pub mod truncate;
 pub mod values_indices;
+pub mod view;
+pub mod watch;
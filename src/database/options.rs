@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use foundationdb::{FdbResult, options::TransactionOption};
+use serde::{Deserialize, Serialize};
+
+use crate::error::SResult;
+
+///Priority a transaction gets its read version with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    #[default]
+    Default,
+    ///Throttled first when the cluster is saturated, for background jobs
+    Batch,
+    ///Skips the ratekeeper, only meant for administrative work
+    SystemImmediate,
+}
+
+///Options that are applied to every attempt of a transaction
+///
+/// Set database wide defaults with [`crate::database::database::Database::set_options`]
+/// or override them for a single call with `transact_with_options`:
+/// ```
+/// use std::time::Duration;
+/// use exotherm::database::options::{Priority, TransactOptions};
+/// let options = TransactOptions::new()
+///     .timeout(Duration::from_secs(2))
+///     .retry_limit(5)
+///     .priority(Priority::Batch)
+///     .tag("reports");
+/// ```
+/// Defaults can also be read from a config file:
+/// ```
+/// use exotherm::database::options::TransactOptions;
+/// let options = TransactOptions::from_toml(r#"
+///     timeout_ms = 5000
+///     retry_limit = 10
+///     priority = "batch"
+///     tags = ["importer"]
+/// "#).unwrap();
+/// assert_eq!(options.get_retry_limit(), Some(10));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransactOptions {
+    timeout_ms: Option<u64>,
+    retry_limit: Option<u32>,
+    max_retry_delay_ms: Option<u64>,
+    priority: Priority,
+    read_only: bool,
+    capture_changes: bool,
+    notify_subscribers: bool,
+    tags: Vec<String>,
+}
+
+impl TransactOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    ///Parse options from toml, every key is optional
+    pub fn from_toml(config: &str) -> SResult<Self> {
+        let options = toml::from_str(config)?;
+        Ok(options)
+    }
+    ///Abort the transaction, including all retries, once it has been running this long
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_ms = Some(timeout.as_millis() as u64);
+        self
+    }
+    ///Give up after this many retries
+    pub fn retry_limit(mut self, limit: u32) -> Self {
+        self.retry_limit = Some(limit);
+        self
+    }
+    ///Upper bound for the backoff between retries
+    pub fn max_retry_delay(mut self, delay: Duration) -> Self {
+        self.max_retry_delay_ms = Some(delay.as_millis() as u64);
+        self
+    }
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+    ///Reject every write made through the transaction
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+    ///Record every put, clear and truncate in the change log, see [`crate::database::database::Database::read_changes`]
+    ///
+    /// Off by default, because each write then also reads the previous version of the row.
+    /// Transactions without it are not logged, enable it in the defaults of
+    /// [`crate::database::database::Database::set_options`] so no write is missed. Index entries
+    /// that point to no row are cleared without a log entry, as no row changes.
+    pub fn capture_changes(mut self, capture: bool) -> Self {
+        self.capture_changes = capture;
+        self
+    }
+    ///Bump the change counter of every written table, which wakes [`crate::database::watch::IndexSubscription`]
+    /// and `watch_table`
+    ///
+    /// Off by default, as the counter is a single key per table that every write would hit.
+    /// Enable it in the defaults of [`crate::database::database::Database::set_options`] when
+    /// tables are subscribed to, writes without it do not wake subscribers.
+    pub fn notify_subscribers(mut self, notify: bool) -> Self {
+        self.notify_subscribers = notify;
+        self
+    }
+    ///Tag the transaction so the cluster can throttle it, tags are at most 16 bytes
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+    pub fn get_retry_limit(&self) -> Option<u32> {
+        self.retry_limit
+    }
+    pub fn get_max_retry_delay(&self) -> Option<Duration> {
+        self.max_retry_delay_ms.map(Duration::from_millis)
+    }
+    pub fn get_priority(&self) -> Priority {
+        self.priority
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    pub fn is_capturing_changes(&self) -> bool {
+        self.capture_changes
+    }
+    pub fn is_notifying_subscribers(&self) -> bool {
+        self.notify_subscribers
+    }
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+    ///Set the options on a transaction. Timeouts, retry limits and tags survive
+    ///`on_error`, so they are only set on the first attempt; the priority is reset
+    ///and has to be set again on every attempt
+    pub(crate) fn apply(
+        &self,
+        trx: &foundationdb::Transaction,
+        first_attempt: bool,
+    ) -> FdbResult<()> {
+        if first_attempt {
+            if let Some(timeout) = self.timeout_ms {
+                trx.set_option(TransactionOption::Timeout(clamp(timeout)))?;
+            }
+            if let Some(limit) = self.retry_limit {
+                trx.set_option(TransactionOption::RetryLimit(clamp(limit as u64)))?;
+            }
+            if let Some(delay) = self.max_retry_delay_ms {
+                trx.set_option(TransactionOption::MaxRetryDelay(clamp(delay)))?;
+            }
+            for tag in &self.tags {
+                trx.set_option(TransactionOption::AutoThrottleTag(tag.clone()))?;
+            }
+        }
+        match self.priority {
+            Priority::Default => {}
+            Priority::Batch => trx.set_option(TransactionOption::PriorityBatch)?,
+            Priority::SystemImmediate => {
+                trx.set_option(TransactionOption::PrioritySystemImmediate)?
+            }
+        }
+        Ok(())
+    }
+}
+
+fn clamp(value: u64) -> i32 {
+    value.min(i32::MAX as u64) as i32
+}
@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use foundationdb::{KeySelector, RangeOption, options::MutationType, options::StreamingMode};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        database::{Database, now_millis},
+        key::{Key, KeyRange},
+        transaction::STransaction,
+    },
+    error::{ExothermError, SResult},
+};
+
+const LEASE: u8 = 0;
+const HEAD: u8 = 1;
+
+#[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug)]
+struct StoredEvent {
+    topic: String,
+    payload: Vec<u8>,
+}
+
+///An event taken from the outbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEvent {
+    ///Commit versionstamp of the enqueueing transaction, unique and increasing
+    pub id: [u8; 12],
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+///Where an [`OutboxWorker`] delivers events to
+///
+/// Delivery is at least once, a batch is delivered again if it was not acknowledged,
+/// so sinks should tolerate duplicates, e.g. by deduplicating on [`OutboxEvent::id`].
+pub trait OutboxSink {
+    fn deliver(&self, events: &[OutboxEvent]) -> impl Future<Output = SResult<()>> + Send;
+}
+
+///Sends events into an in-process channel
+pub struct ChannelSink {
+    sender: mpsc::UnboundedSender<OutboxEvent>,
+}
+
+impl ChannelSink {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<OutboxEvent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (ChannelSink { sender }, receiver)
+    }
+}
+
+impl OutboxSink for ChannelSink {
+    async fn deliver(&self, events: &[OutboxEvent]) -> SResult<()> {
+        for event in events {
+            self.sender
+                .send(event.clone())
+                .map_err(|e| ExothermError::Sink(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl STransaction {
+    ///Add an event to the outbox, it is only published if the transaction commits
+    /// ```ignore
+    ///     db.transact(|transaction| async move {
+    ///         transaction.insert(&order, id).await?;
+    ///         transaction.enqueue("order_created", id.as_bytes())?;
+    ///         Ok(())
+    ///     })
+    ///     .await?;
+    /// ```
+    pub fn enqueue(&self, topic: &str, payload: &[u8]) -> SResult<()> {
+        self.writable()?;
+        let event = StoredEvent {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+        };
+        let value = rkyv::to_bytes::<rkyv::rancor::Error>(&event)?;
+        self.set_versionstamped(Key::outbox_prefix(self.tenant.clone())?, &value)?;
+        let head = Key::outbox_meta(self.tenant.clone(), HEAD)?;
+        self.trx
+            .atomic_op(&head, &1u64.to_le_bytes(), MutationType::Add);
+        Ok(())
+    }
+}
+
+///Lease owner followed by the expiry in milliseconds since the epoch
+fn decode_lease(value: &[u8]) -> Option<(Uuid, u64)> {
+    let owner = Uuid::from_slice(value.get(..16)?).ok()?;
+    let expires = u64::from_le_bytes(value.get(16..24)?.try_into().ok()?);
+    Some((owner, expires))
+}
+
+///Drains the outbox of a tenant into a sink
+///
+/// Workers take a lease on the whole outbox, so only one of them delivers at a time and events
+/// keep their order. A worker that dies holding the lease is replaced once the lease expires,
+/// which redelivers its unacknowledged batch.
+pub struct OutboxWorker<'a, S> {
+    db: &'a Database,
+    sink: S,
+    id: Uuid,
+    batch: usize,
+    lease: Duration,
+}
+
+impl<'a, S: OutboxSink> OutboxWorker<'a, S> {
+    pub fn new(db: &'a Database, sink: S) -> Self {
+        OutboxWorker {
+            db,
+            sink,
+            id: Uuid::new_v4(),
+            batch: 100,
+            lease: Duration::from_secs(30),
+        }
+    }
+    ///Maximum number of events per batch
+    pub fn batch(mut self, batch: usize) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+    ///How long a batch may take to deliver before another worker takes over
+    pub fn lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+    ///Lease, deliver and acknowledge one batch, returns the number of delivered events
+    ///
+    /// Returns `0` if the outbox is empty or another worker holds the lease.
+    pub async fn run_once(&self) -> SResult<usize> {
+        let events = self.take().await?;
+        let Some(last) = events.last() else {
+            return Ok(0);
+        };
+        self.sink.deliver(&events).await?;
+        self.ack(last.id).await?;
+        Ok(events.len())
+    }
+    ///Deliver events until an error occurs, waiting for new ones when the outbox is empty
+    pub async fn run(&self) -> SResult<()> {
+        loop {
+            let changed = self
+                .db
+                .transact(|transaction| async move {
+                    let head = Key::outbox_meta(transaction.tenant.clone(), HEAD)?;
+                    Ok(transaction.trx.watch(&head))
+                })
+                .await?;
+            if self.run_once().await? > 0 {
+                continue;
+            }
+            //Wake up when a lease of another worker expires
+            tokio::select! {
+                res = changed => res?,
+                _ = tokio::time::sleep(self.lease) => {}
+            }
+        }
+    }
+    async fn take(&self) -> SResult<Vec<OutboxEvent>> {
+        let (id, lease, batch) = (self.id, self.lease, self.batch);
+        self.db
+            .transact(|transaction| async move {
+                transaction.writable()?;
+                let lease_key = Key::outbox_meta(transaction.tenant.clone(), LEASE)?;
+                let now = now_millis();
+                if let Some(value) = transaction.trx.get(&lease_key, false).await? {
+                    match decode_lease(&value) {
+                        Some((owner, expires)) if owner != id && expires > now => {
+                            return Ok(Vec::new());
+                        }
+                        _ => {}
+                    }
+                }
+                let prefix = Key::outbox_prefix(transaction.tenant.clone())?;
+                let range = KeyRange::prefix(prefix.clone());
+                let opt = RangeOption {
+                    begin: KeySelector::first_greater_or_equal(range.begin.as_slice()),
+                    end: KeySelector::first_greater_or_equal(range.end.as_slice()),
+                    limit: Some(batch),
+                    mode: StreamingMode::WantAll,
+                    ..RangeOption::default()
+                };
+                let values = transaction.trx.get_range(&opt, 1, false).await?;
+                let mut events = Vec::with_capacity(values.len());
+                for kv in &values {
+                    let mut event_id = [0u8; 12];
+                    event_id.copy_from_slice(&kv.key()[prefix.len()..prefix.len() + 12]);
+                    let mut aligned = rkyv::util::AlignedVec::<16>::with_capacity(kv.value().len());
+                    aligned.extend_from_slice(kv.value());
+                    let event = rkyv::from_bytes::<StoredEvent, rkyv::rancor::Error>(&aligned)?;
+                    events.push(OutboxEvent {
+                        id: event_id,
+                        topic: event.topic,
+                        payload: event.payload,
+                    });
+                }
+                if !events.is_empty() {
+                    let mut value = id.as_bytes().to_vec();
+                    value.extend_from_slice(&(now + lease.as_millis() as u64).to_le_bytes());
+                    transaction.trx.set(&lease_key, &value);
+                }
+                Ok(events)
+            })
+            .await
+    }
+    ///Remove every event up to and including `last` and release the lease
+    async fn ack(&self, last: [u8; 12]) -> SResult<()> {
+        let id = self.id;
+        self.db
+            .transact(|transaction| async move {
+                transaction.writable()?;
+                let lease_key = Key::outbox_meta(transaction.tenant.clone(), LEASE)?;
+                let value = transaction.trx.get(&lease_key, false).await?;
+                match value.as_deref().and_then(decode_lease) {
+                    Some((owner, _)) if owner == id => {}
+                    _ => return Err(ExothermError::LeaseLost { worker: id }),
+                }
+                let begin = Key::outbox_prefix(transaction.tenant.clone())?;
+                let mut end = begin.clone();
+                end.extend_from_slice(&last);
+                end.push(0);
+                transaction.trx.clear_range(&begin, &end);
+                transaction.trx.clear(&lease_key);
+                Ok(())
+            })
+            .await
+    }
+}
@@ -1,7 +1,10 @@
 use uuid::Uuid;
 
 use crate::{
-    database::values_indices::{ArchivedRow, DbValue, IndexableValue, Row},
+    database::{
+        blobstore::Blob,
+        values_indices::{ArchivedRow, DbValue, IndexableValue, Row},
+    },
     error::{ConvertError, SResult},
 };
 
@@ -18,6 +21,30 @@ use super::key::{Key, Tenant};
 /// });
 /// ```
 /// Each column gets a number like in ProtoBuf; so that you can identify each column even if something has been changed
+///
+/// # Derive
+/// Alternatively derive it on a regular struct, which keeps rustdoc, other derives and field attributes working
+/// ```
+/// use exotherm::Record;
+/// #[derive(Debug, Clone, Record)]
+/// pub struct Person {
+///     #[exo(id = 0, index = "name_index")]
+///     pub name: String,
+///     #[exo(id = 1)]
+///     pub password: String,
+/// }
+/// ```
+/// Column numbers are checked at compile time
+/// ```compile_fail
+/// use exotherm::Record;
+/// #[derive(Record)]
+/// pub struct Person {
+///     #[exo(id = 0)]
+///     pub name: String,
+///     #[exo(id = 0)]
+///     pub password: String,
+/// }
+/// ```
 pub trait RecordStruct {
     type Decoded;
     /*fn append_corpus_key(&self, key: &mut Vec<u8>, pk: Uuid) {
@@ -47,12 +74,23 @@ pub trait RecordStruct {
         let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&row)?;
         Ok(bytes)
     }
+    ///Name of the table the rows are stored under, including the namespace if there is one
     fn name() -> &'static str;
     ///Macro generated function that returns a padded vector of every value
     fn corpus(&self) -> Vec<DbValue>; //Result<rkyv::util::AlignedVec, rkyv::rancor::Error>;
     ///Macro generated function that returns all active indices on the schema with corresponding values
     fn indices(&self, uuid: uuid::Uuid) -> Vec<Key>; //Vec<(usize, crate::values_indices::IndexableValue)>;
     fn tname(&self) -> &'static str;
+    ///Macro generated, whether a column keeps its data in the blobstore
+    ///
+    /// Writing such a row reads the previous version to delete blobs it no longer references.
+    fn has_blobs() -> bool {
+        false
+    }
+    ///Macro generated function that returns every blob column that is set
+    fn blobs(&self) -> Vec<&Blob> {
+        Vec::new()
+    }
     ///Macro generated function that fills the struct with values from a corpus vec
     fn deserialize(from: Vec<DbValue>) -> Result<Self::Decoded, ConvertError>;
     fn decode(from: &[u8]) -> SResult<Self::Decoded> {
@@ -66,6 +104,17 @@ pub trait RecordStruct {
         Ok(deserialize)
     }
 }
+///Implemented by the Record derive, gives typed setters that remember which columns changed
+///
+/// Used by [`crate::database::transaction::STransaction::update`]
+pub trait Patchable: RecordStruct<Decoded = Self> + Sized {
+    type Patch<'a>
+    where
+        Self: 'a;
+    fn patch(&mut self) -> Self::Patch<'_>;
+    ///Column numbers that were set on the patch
+    fn changed(patch: &Self::Patch<'_>) -> Vec<u16>;
+}
 pub fn pad_indices(input: Vec<(usize, DbValue)>) -> Vec<DbValue> {
     let mut max = 0;
     for (idx, _) in &input {
@@ -108,10 +157,11 @@ pub struct IndexAddress {
     pub idx: usize,
     pub value: IndexableValue,
 }
-#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+///Where the data of a [`Blob`] column is stored, this is all the row keeps
+#[derive(Debug, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct BlobstoreAddress {
-    bucket: String,
-    id: Uuid,
+    pub bucket: String,
+    pub id: Uuid,
 }
 
 impl IndexAddress {
@@ -137,67 +187,55 @@ impl IndexAddress {
 }
 
 //($name:ident { $($field_num:literal -> $field:ident :  [$($index_name:ident : $index_type:ty)?]  $ty:ty ),* $(,)? })
+///Declare a struct together with its `RecordStruct` implementation
+///
+/// The table name defaults to the struct name. Set a stable name with `as` and
+/// optionally put it into a namespace with `in`, so renaming the struct keeps its data:
+/// ```
+/// use exotherm::schema;
+/// schema!(Person in "auth" as "people" {
+///    0 -> name: [name_index] String,
+///    1 -> password:[] String,
+/// });
+/// ```
+///
+/// Names must not be empty or contain `.`, so no two tables share their keys:
+/// ```compile_fail
+/// use exotherm::schema;
+/// schema!(Person in "auth.v2" as "people" {
+///    0 -> name: [] String,
+/// });
+/// ```
+///
+/// The struct is generated with `#[derive(Record)]`, so every column also gets a `project_<field>()` projection.
+/// Columns of type [`Blob`] only store an address in the row, their data is written to the blobstore with the row:
+/// ```
+/// use exotherm::{database::blobstore::Blob, schema};
+/// schema!(Document {
+///    0 -> title: [] String,
+///    1 -> body: [] Blob,
+///    2 -> thumbnail: [] Option<Blob>,
+/// });
+/// ```
+/// Adding `ref` with a second name also generates a borrowed view of the row, see [`crate::database::view::ArchivedRecord`]:
+/// ```
+/// use exotherm::schema;
+/// schema!(Person ref PersonRef {
+///    0 -> name: [name_index] String,
+///    1 -> password:[] String,
+/// });
+/// ```
 #[macro_export]
 macro_rules! schema {
-    ($name:ident { $($field_num:literal -> $field:ident :  [$($index_name:ident)?]  $ty:ty ),* $(,)? }) => {
+    ($name:ident $(in $namespace:literal)? $(as $table:literal)? $(ref $view:ident)? { $($field_num:literal -> $field:ident :  [$($index_name:ident)?]  $ty:ty ),* $(,)? }) => {
         ///This struct is represents an automatically generated Exotherm schema
-        #[derive(Debug)]
+        #[derive(Debug, $crate::Record)]
+        #[exo($(namespace = $namespace,)? $(table = $table,)? $(view = $view,)?)]
         pub struct $name {
-            $(pub $field: $ty),*
-        }
-        impl $name {
-
             $(
-                $(
-                    /// Generates an index key for a column
-                    /// Function generated by exotherm
-                    pub fn $index_name(row: uuid::Uuid, value: &$ty) -> $crate::database::key::Key{
-                        use $crate::database::record::RecordStruct;
-                        use $crate::database::values_indices::*;
-                        use $crate::database::key::*;
-                        $crate::database::key::Key::new_index(
-                            Tenant::Unset,
-                            Self::name(),
-                            $field_num,
-                            value.index(),
-                            row
-                        )
-                    }
-                )?
-            )*
-        }
-        impl $crate::database::record::RecordStruct for $name {
-            type Decoded = $name;
-            fn name() -> &'static str {
-                stringify!($name)
-            }
-            fn corpus(&self) -> Vec<$crate::database::values_indices::DbValue>
-            {
-                use $crate::database::values_indices::*;
-                let unpadded = vec![
-                    $(($field_num,self.$field.encode_db())),*
-                ];
-                let padded = $crate::database::record::pad_indices(unpadded);
-                padded
-            }
-            fn indices(&self, row: uuid::Uuid) ->  Vec<$crate::database::key::Key>//Vec<(usize, IndexableValue)>
-            {
-                vec![$(
-                    $(
-                        //Self::$index_name($crate::record::Query::GetIndex(self.$field.clone(), row)),
-                        Self::$index_name(row, &self.$field),
-                    )?
-                )*]
-            }
-            fn tname(&self) -> &'static str {
-                stringify!($name)
-            }
-            fn deserialize(from: Vec<$crate::database::values_indices::DbValue>) -> Result<Self, $crate::error::ConvertError> {
-                let res = $name {
-                    $($field: from[$field_num].clone().try_into()?),*
-                };
-                Ok(res)
-            }
+                #[exo(id = $field_num $(, index = $index_name)?)]
+                pub $field: $ty
+            ),*
         }
     };
 }
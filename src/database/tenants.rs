@@ -0,0 +1,298 @@
+use foundationdb::{KeySelector, RangeOption, future::FdbValues, options::StreamingMode};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    database::{
+        blobstore::decode_count,
+        database::{Database, now_millis},
+        key::{Key, KeyRange, Purpose, Tenant},
+    },
+    error::{ExothermError, SResult},
+};
+
+///Tenant registered in the catalog
+#[derive(Debug, Clone)]
+pub struct TenantInfo {
+    pub tenant: Tenant,
+    ///Milliseconds since the unix epoch
+    pub created_at: u64,
+}
+
+///Storage of a tenant as estimated by FoundationDB from its shard samples
+///
+/// Estimates of small tenants are inaccurate and can be 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TenantStats {
+    pub estimated_size: u64,
+    ///Part of `estimated_size` taken by blob shards
+    pub estimated_blob_size: u64,
+}
+
+///Catalog entries and exported keys read per transaction
+const PAGE: usize = 10_000;
+///Bytes written per transaction while importing, well below the transaction size limit
+const IMPORT_BATCH: usize = 1 << 20;
+///Start of every export, followed by the version of the format
+const EXPORT_MAGIC: &[u8] = b"exotherm";
+const EXPORT_VERSION: u8 = 1;
+///Largest key and value FoundationDB accepts, longer lengths in an export are corrupt
+const MAX_KEY_SIZE: usize = 10_000;
+const MAX_VALUE_SIZE: usize = 100_000;
+#[cfg(feature = "native-tenants")]
+const TENANT_NOT_FOUND: i32 = 2131;
+#[cfg(feature = "native-tenants")]
+const TENANT_ALREADY_EXISTS: i32 = 2132;
+
+impl Database {
+    ///Register a tenant in the catalog, returns `false` if it was already registered
+    ///
+    /// With native tenants the FoundationDB tenant is created as well.
+    pub async fn create_tenant(&self, tenant: &Tenant) -> SResult<bool> {
+        #[cfg(feature = "native-tenants")]
+        if self.native_tenants {
+            match self.create_native_tenant(tenant).await {
+                Err(ExothermError::FoundationDB(e)) if e.code() == TENANT_ALREADY_EXISTS => (),
+                result => result?,
+            }
+        }
+        let key = tenant.catalog_key()?;
+        let key = &key;
+        let created = self
+            .fdb
+            .run(|trx, _maybe_committed| async move {
+                if trx.get(key, false).await?.is_some() {
+                    return Ok(false);
+                }
+                trx.set(key, &now_millis().to_le_bytes());
+                Ok(true)
+            })
+            .await?;
+        Ok(created)
+    }
+    ///Every tenant in the catalog, ordered by name and then by id
+    pub async fn list_tenants(&self) -> SResult<Vec<TenantInfo>> {
+        let range = Key::catalog_range();
+        let mut tenants = Vec::new();
+        let mut begin = range.begin.clone();
+        loop {
+            let (page, more) = self.read_page(&begin, &range.end).await?;
+            for (key, value) in &page {
+                tenants.push(TenantInfo {
+                    tenant: Tenant::from_catalog_key(key)?,
+                    created_at: decode_count(value),
+                });
+            }
+            match page.last() {
+                Some((last, _)) if more => {
+                    begin = last.clone();
+                    begin.push(0);
+                }
+                _ => return Ok(tenants),
+            }
+        }
+    }
+    ///Estimated storage of a tenant, whether it is registered or not
+    pub async fn tenant_stats(&self, tenant: &Tenant) -> SResult<TenantStats> {
+        let everything = KeyRange::prefix(Key::tenant_prefix(tenant.clone())?);
+        let blobs = Key::purpose_range(tenant.clone(), "", Purpose::BLOB_TAG)?;
+        self.transact_with_tenant(tenant.clone(), |transaction| {
+            let (everything, blobs) = (&everything, &blobs);
+            async move {
+                let estimated_size = transaction
+                    .trx
+                    .get_estimated_range_size_bytes(&everything.begin, &everything.end)
+                    .await?;
+                let estimated_blob_size = transaction
+                    .trx
+                    .get_estimated_range_size_bytes(&blobs.begin, &blobs.end)
+                    .await?;
+                Ok(TenantStats {
+                    estimated_size: estimated_size.max(0) as u64,
+                    estimated_blob_size: estimated_blob_size.max(0) as u64,
+                })
+            }
+        })
+        .await
+    }
+    ///Delete all data of a tenant and remove it from the catalog, returns `false` if it was not registered
+    ///
+    /// The data is deleted even if the tenant is not registered. With native tenants the
+    /// FoundationDB tenant is deleted as well.
+    pub async fn delete_tenant(&self, tenant: &Tenant) -> SResult<bool> {
+        self.transact_with_tenant(tenant.clone(), |transaction| async move {
+            transaction.clear_tenant(tenant.clone())
+        })
+        .await?;
+        #[cfg(feature = "native-tenants")]
+        if self.native_tenants {
+            match self.delete_native_tenant(tenant).await {
+                Err(ExothermError::FoundationDB(e)) if e.code() == TENANT_NOT_FOUND => (),
+                result => result?,
+            }
+        }
+        let key = tenant.catalog_key()?;
+        let key = &key;
+        let registered = self
+            .fdb
+            .run(|trx, _maybe_committed| async move {
+                let registered = trx.get(key, false).await?.is_some();
+                trx.clear(key);
+                Ok(registered)
+            })
+            .await?;
+        Ok(registered)
+    }
+    ///Write every key of a tenant to `writer`, returns the number of exported keys
+    ///
+    /// Keys are written without the tenant prefix, so [`Self::import_tenant`] can restore them
+    /// into any tenant. The tenant is read in several transactions, stop writing to it first
+    /// for a consistent copy:
+    /// ```ignore
+    ///     let mut file = tokio::fs::File::create("tenant.export").await?;
+    ///     db.export_tenant(&Tenant::named(name), &mut file).await?;
+    ///     file.flush().await?;
+    /// ```
+    pub async fn export_tenant<W: AsyncWrite + Unpin>(
+        &self,
+        tenant: &Tenant,
+        writer: &mut W,
+    ) -> SResult<u64> {
+        let range = KeyRange::prefix(Key::tenant_prefix(tenant.clone())?);
+        writer.write_all(EXPORT_MAGIC).await?;
+        writer.write_u8(EXPORT_VERSION).await?;
+        let mut exported = 0;
+        let mut begin = range.begin.clone();
+        loop {
+            let (page, more) = self
+                .transact_with_tenant(tenant.clone(), |transaction| {
+                    let (begin, range) = (&begin, &range);
+                    async move {
+                        let opt = RangeOption {
+                            begin: KeySelector::first_greater_or_equal(begin.as_slice()),
+                            end: KeySelector::first_greater_or_equal(range.end.as_slice()),
+                            limit: Some(PAGE),
+                            mode: StreamingMode::WantAll,
+                            ..RangeOption::default()
+                        };
+                        let values = transaction.trx.get_range(&opt, 1, true).await?;
+                        Ok(key_values(&values))
+                    }
+                })
+                .await?;
+            for (key, value) in &page {
+                let key = &key[range.begin.len()..];
+                writer.write_u32_le(key.len() as u32).await?;
+                writer.write_all(key).await?;
+                writer.write_u32_le(value.len() as u32).await?;
+                writer.write_all(value).await?;
+            }
+            exported += page.len() as u64;
+            match page.last() {
+                Some((last, _)) if more => {
+                    begin = last.clone();
+                    begin.push(0);
+                }
+                _ => break,
+            }
+        }
+        writer.flush().await?;
+        Ok(exported)
+    }
+    ///Restore an export of [`Self::export_tenant`] into a tenant and register it, returns the number of imported keys
+    ///
+    /// Existing keys are overwritten but not deleted, import into an empty tenant to get an exact copy.
+    /// The keys are written in several transactions, so a failed import leaves a partial copy behind.
+    /// Keys and values longer than FoundationDB allows fail with [`std::io::ErrorKind::InvalidData`].
+    pub async fn import_tenant<R: AsyncRead + Unpin>(
+        &self,
+        tenant: &Tenant,
+        reader: &mut R,
+    ) -> SResult<u64> {
+        let mut header = [0; EXPORT_MAGIC.len() + 1];
+        reader.read_exact(&mut header).await?;
+        if &header[..EXPORT_MAGIC.len()] != EXPORT_MAGIC
+            || header[EXPORT_MAGIC.len()] != EXPORT_VERSION
+        {
+            return Err(corrupt_export("not a tenant export of this version"));
+        }
+        self.create_tenant(tenant).await?;
+        let prefix = Key::tenant_prefix(tenant.clone())?;
+        let mut imported = 0;
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        loop {
+            let mut len = [0; 4];
+            let end = reader.read(&mut len[..1]).await? == 0;
+            if !end {
+                reader.read_exact(&mut len[1..]).await?;
+                let key_len = prefix.len() + u32::from_le_bytes(len) as usize;
+                if key_len > MAX_KEY_SIZE {
+                    return Err(corrupt_export("key longer than 10KB"));
+                }
+                let mut key = prefix.clone();
+                key.resize(key_len, 0);
+                reader.read_exact(&mut key[prefix.len()..]).await?;
+                let value_len = reader.read_u32_le().await? as usize;
+                if value_len > MAX_VALUE_SIZE {
+                    return Err(corrupt_export("value longer than 100KB"));
+                }
+                let mut value = vec![0; value_len];
+                reader.read_exact(&mut value).await?;
+                batch_size += key.len() + value.len();
+                batch.push((key, value));
+            }
+            if batch_size >= IMPORT_BATCH || (end && !batch.is_empty()) {
+                self.transact_with_tenant(tenant.clone(), |transaction| {
+                    let batch = &batch;
+                    async move {
+                        transaction.writable()?;
+                        for (key, value) in batch {
+                            transaction.trx.set(key, value);
+                        }
+                        Ok(())
+                    }
+                })
+                .await?;
+                imported += batch.len() as u64;
+                batch.clear();
+                batch_size = 0;
+            }
+            if end {
+                return Ok(imported);
+            }
+        }
+    }
+    ///One page of keys and values outside of every tenant, and whether the range has more
+    async fn read_page(&self, begin: &[u8], end: &[u8]) -> SResult<(KeyValues, bool)> {
+        let page = self
+            .fdb
+            .run(|trx, _maybe_committed| async move {
+                let opt = RangeOption {
+                    begin: KeySelector::first_greater_or_equal(begin),
+                    end: KeySelector::first_greater_or_equal(end),
+                    limit: Some(PAGE),
+                    mode: StreamingMode::WantAll,
+                    ..RangeOption::default()
+                };
+                let values = trx.get_range(&opt, 1, false).await?;
+                Ok(key_values(&values))
+            })
+            .await?;
+        Ok(page)
+    }
+}
+
+type KeyValues = Vec<(Vec<u8>, Vec<u8>)>;
+
+///Owned copy of a page, with whether FoundationDB has more keys after it
+fn key_values(values: &FdbValues) -> (KeyValues, bool) {
+    let page = values
+        .iter()
+        .map(|kv| (kv.key().to_vec(), kv.value().to_vec()))
+        .collect();
+    (page, values.more())
+}
+
+fn corrupt_export(reason: &str) -> ExothermError {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason).into()
+}
@@ -1,19 +1,87 @@
-use foundationdb::{FdbBindingError, RangeOption, options::StreamingMode};
+use foundationdb::{
+    RangeOption,
+    options::{ConflictRangeType, MutationType, StreamingMode},
+};
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use serde::{Serialize, de::DeserializeOwned};
 use uuid::Uuid;
 
 use crate::{
-    database::{key::Purpose, record::RecordStruct},
-    error::{ExothermError, SResult},
+    database::{
+        blobstore::decode_count,
+        changes::ChangeOp,
+        database::now_millis,
+        key::Purpose,
+        record::{Patchable, RecordStruct},
+        values_indices::DbValue,
+        view::{ArchivedRecord, Projection},
+    },
+    error::{ConvertError, ExothermError, SResult},
 };
 
-use super::key::{Key, Tenant};
+use super::key::{Key, KeyRange, Tenant};
 
 #[allow(dead_code)]
 pub struct STransaction {
     pub(super) trx: foundationdb::RetryableTransaction,
     pub maybe_commited: bool,
     pub(super) tenant: Tenant,
+    pub(super) read_only: bool,
+    pub(super) capture_changes: bool,
+    pub(super) notify_subscribers: bool,
+    ///Orders versionstamped keys written by the same transaction
+    pub(super) stamp_order: AtomicU16,
+}
+async fn watch(future: impl Future<Output = foundationdb::FdbResult<()>>) -> SResult<()> {
+    future.await?;
+    Ok(())
+}
+
+///Reads that do not add read conflicts, returned by [`STransaction::snapshot`]
+///
+/// Concurrent writes to what was read here do not make the transaction retry.
+/// Use them for data where a slightly stale value is fine, e.g. statistics or listings.
+/// ```ignore
+///     let person: Option<Person> = transaction.snapshot().get_value(id).await?;
+/// ```
+pub struct Snapshot<'a>(&'a STransaction);
+
+impl<'a> Snapshot<'a> {
+    pub async fn get_value<T: RecordStruct<Decoded = T>>(&self, pk: Uuid) -> SResult<Option<T>> {
+        self.0.read_value(pk, true).await
+    }
+    pub async fn get_versioned<T: RecordStruct<Decoded = T>>(
+        &self,
+        pk: Uuid,
+    ) -> SResult<Option<Versioned<T>>> {
+        self.0.read_versioned(pk, true).await
+    }
+    pub async fn get_version<T: RecordStruct>(&self, pk: Uuid) -> SResult<u64> {
+        self.0.read_version::<T>(pk, true).await
+    }
+    pub async fn get_archived<T: RecordStruct>(&self, pk: Uuid) -> SResult<Option<ArchivedRecord>> {
+        self.0.read_archived::<T>(pk, true).await
+    }
+    pub async fn get_columns<T: RecordStruct>(
+        &self,
+        pk: Uuid,
+        columns: &[u16],
+    ) -> SResult<Option<Vec<DbValue>>> {
+        self.0.read_columns::<T>(pk, columns, true).await
+    }
+    pub async fn project<T, C>(&self, pk: Uuid, projection: Projection<T, C>) -> SResult<Option<C>>
+    where
+        T: RecordStruct,
+        C: TryFrom<DbValue, Error = ConvertError>,
+    {
+        self.0.read_projection(pk, projection, true).await
+    }
+    pub async fn query_index(&self, query: Query, reverse: bool) -> SResult<PageResult<'a>> {
+        self.0.read_index(query, reverse, true).await
+    }
 }
+
 #[allow(dead_code)]
 pub enum Query {
     Equal(Key),
@@ -23,6 +91,14 @@ pub enum Query {
     WantAll(Key),
 }
 impl Query {
+    ///Encoded index keys the query matches
+    pub(crate) fn key_range(self, tenant: Tenant) -> SResult<KeyRange> {
+        let Range(from, to) = self.into_range(tenant)?;
+        Ok(KeyRange {
+            begin: from.generate()?,
+            end: to.generate()?,
+        })
+    }
     fn into_range(self, tenant: Tenant) -> SResult<Range> {
         match self {
             Query::Equal(Key {
@@ -32,7 +108,8 @@ impl Query {
                 row: _,
             }) => {
                 if let Purpose::Index(id, value) = purpose {
-                    let from = Key::new_index(tenant, table, id, value.clone(), Uuid::nil());
+                    let from =
+                        Key::new_index(tenant.clone(), table, id, value.clone(), Uuid::nil());
                     let to = Key::new_index(tenant, table, id, value, Uuid::max());
                     Ok(Range(from, to))
                 } else {
@@ -58,7 +135,7 @@ impl Query {
                     if id != id1 {
                         return Err(ExothermError::UnequalColumns);
                     }
-                    let from = Key::new_index(tenant, table, id, value1, Uuid::nil());
+                    let from = Key::new_index(tenant.clone(), table, id, value1, Uuid::nil());
                     let to = Key::new_index(tenant, table, id, value2, Uuid::max());
                     Ok(Range(from, to))
                 } else {
@@ -73,7 +150,7 @@ impl Query {
             }) => {
                 if let Purpose::Index(id, value) = purpose {
                     let (_, max) = value.bounds();
-                    let from = Key::new_index(tenant, table, id, value, Uuid::nil());
+                    let from = Key::new_index(tenant.clone(), table, id, value, Uuid::nil());
                     let to = Key::new_index(tenant, table, id, max, Uuid::max());
                     Ok(Range(from, to))
                 } else {
@@ -88,7 +165,7 @@ impl Query {
             }) => {
                 if let Purpose::Index(id, value) = purpose {
                     let (min, _) = value.bounds();
-                    let from = Key::new_index(tenant, table, id, min, Uuid::nil());
+                    let from = Key::new_index(tenant.clone(), table, id, min, Uuid::nil());
                     let to = Key::new_index(tenant, table, id, value, Uuid::max());
                     Ok(Range(from, to))
                 } else {
@@ -103,7 +180,7 @@ impl Query {
             }) => {
                 if let Purpose::Index(id, value) = purpose {
                     let (min, max) = value.bounds();
-                    let from = Key::new_index(tenant, table, id, min, Uuid::nil());
+                    let from = Key::new_index(tenant.clone(), table, id, min, Uuid::nil());
                     let to = Key::new_index(tenant, table, id, max, Uuid::max());
                     Ok(Range(from, to))
                 } else {
@@ -124,106 +201,538 @@ pub struct PageResult<'a> {
     next: Option<RangeOption<'a>>,
 }
 
+///A row together with the version it was read at, see [`STransaction::put_value_if_version`]
+#[derive(Debug, Clone)]
+pub struct Versioned<T> {
+    pub record: T,
+    pub version: u64,
+}
+
 impl STransaction {
-    pub async fn clear_value<T: RecordStruct<Decoded = T>>(
-        &self,
-        pk: Uuid,
-    ) -> Result<bool, FdbBindingError> {
-        let key = T::corpus_key(self.tenant, pk)
-            .generate()
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
+    ///Read without adding read conflicts, see [`Snapshot`]
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot(self)
+    }
+    ///Make the transaction conflict on `from..to` as if it read or wrote that range
+    ///
+    /// Useful after a snapshot read whose result only partially matters, or to make concurrent
+    /// transactions conflict without writing anything. Keys are scoped to the transaction's tenant.
+    pub fn add_conflict_range(&self, from: Key, to: Key, kind: ConflictRangeType) -> SResult<()> {
+        let from = self.scoped_key(from)?;
+        let to = self.scoped_key(to)?;
+        self.trx.add_conflict_range(&from, &to, kind)?;
+        Ok(())
+    }
+    ///Make the transaction conflict on a single key
+    pub fn add_conflict_key(&self, key: Key, kind: ConflictRangeType) -> SResult<()> {
+        let from = self.scoped_key(key)?;
+        let mut to = from.clone();
+        to.push(0);
+        self.trx.add_conflict_range(&from, &to, kind)?;
+        Ok(())
+    }
+    pub async fn clear_value<T: RecordStruct<Decoded = T>>(&self, pk: Uuid) -> SResult<bool> {
+        let key = T::corpus_key(self.tenant.clone(), pk).generate()?;
         //println!("GET: {:?}", key);
         if let Some(value) = &self.trx.get(&key, false).await? {
             //println!("GET VALUE {:?}", value.to_vec());
-            let d =
-                T::decode(&value).map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
+            let d = T::decode(value)?;
             let indices = d.indices(pk);
             for index in indices {
                 self.clear_index(index)?;
             }
-            self.clear_corpus(pk, &d)?;
+            self.clear_corpus(pk, &d).await?;
             Ok(true)
         } else {
             Ok(false)
         }
     }
-    pub async fn get_value<T: RecordStruct<Decoded = T>>(
+    pub async fn get_value<T: RecordStruct<Decoded = T>>(&self, pk: Uuid) -> SResult<Option<T>> {
+        self.read_value(pk, false).await
+    }
+    async fn read_value<T: RecordStruct<Decoded = T>>(
         &self,
         pk: Uuid,
-    ) -> Result<Option<T>, FdbBindingError> {
-        let key = T::corpus_key(self.tenant, pk)
-            .generate()
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
+        snapshot: bool,
+    ) -> SResult<Option<T>> {
+        let key = T::corpus_key(self.tenant.clone(), pk).generate()?;
         //println!("GET: {:?}", key);
-        if let Some(value) = &self.trx.get(&key, false).await? {
+        if let Some(value) = &self.trx.get(&key, snapshot).await? {
             //println!("GET VALUE {:?}", value.to_vec());
-            let d =
-                T::decode(&value).map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
+            let d = T::decode(value)?;
             Ok(Some(d))
         } else {
             Ok(None)
         }
     }
-    pub async fn put_value(
+    ///Read a row together with its version
+    pub async fn get_versioned<T: RecordStruct<Decoded = T>>(
         &self,
-        record: &impl RecordStruct,
         pk: Uuid,
-    ) -> Result<(), FdbBindingError> {
+    ) -> SResult<Option<Versioned<T>>> {
+        self.read_versioned(pk, false).await
+    }
+    async fn read_versioned<T: RecordStruct<Decoded = T>>(
+        &self,
+        pk: Uuid,
+        snapshot: bool,
+    ) -> SResult<Option<Versioned<T>>> {
+        let Some(record) = self.read_value::<T>(pk, snapshot).await? else {
+            return Ok(None);
+        };
+        let version = self.read_version::<T>(pk, snapshot).await?;
+        Ok(Some(Versioned { record, version }))
+    }
+    ///Number of writes to a row, `0` if it was never written
+    ///
+    /// Every put, update and clear increments it. It is kept when the row is cleared so that a
+    /// version read before a delete can not match a row that was created again afterwards.
+    pub async fn get_version<T: RecordStruct>(&self, pk: Uuid) -> SResult<u64> {
+        self.read_version::<T>(pk, false).await
+    }
+    async fn read_version<T: RecordStruct>(&self, pk: Uuid, snapshot: bool) -> SResult<u64> {
+        let key = Key::new_version(self.tenant.clone(), T::name(), pk).generate()?;
+        let version = self.trx.get(&key, snapshot).await?;
+        Ok(version.map_or(0, |value| decode_count(&value)))
+    }
+    ///Write a row only if nobody else wrote it since `expected` was read with [`Self::get_versioned`]
+    ///
+    /// Use `0` as expected version to create a row that must not exist yet.
+    /// Fails with [`ExothermError::VersionConflict`] otherwise.
+    pub async fn put_value_if_version<T: RecordStruct<Decoded = T>>(
+        &self,
+        record: &T,
+        pk: Uuid,
+        expected: u64,
+    ) -> SResult<()> {
+        let found = self.get_version::<T>(pk).await?;
+        if found != expected {
+            return Err(ExothermError::VersionConflict {
+                table: T::name(),
+                pk,
+                expected,
+                found,
+            });
+        }
+        self.upsert(record, pk).await?;
+        Ok(())
+    }
+    ///Read a row without deserializing it, use [`ArchivedRecord::view`] to borrow its columns
+    pub async fn get_archived<T: RecordStruct>(&self, pk: Uuid) -> SResult<Option<ArchivedRecord>> {
+        self.read_archived::<T>(pk, false).await
+    }
+    async fn read_archived<T: RecordStruct>(
+        &self,
+        pk: Uuid,
+        snapshot: bool,
+    ) -> SResult<Option<ArchivedRecord>> {
+        let key = T::corpus_key(self.tenant.clone(), pk).generate()?;
+        let value = self.trx.get(&key, snapshot).await?;
+        Ok(value.map(ArchivedRecord::from_fdb))
+    }
+    ///Read only some columns of a row, in the order of `columns`
+    pub async fn get_columns<T: RecordStruct>(
+        &self,
+        pk: Uuid,
+        columns: &[u16],
+    ) -> SResult<Option<Vec<DbValue>>> {
+        self.read_columns::<T>(pk, columns, false).await
+    }
+    async fn read_columns<T: RecordStruct>(
+        &self,
+        pk: Uuid,
+        columns: &[u16],
+        snapshot: bool,
+    ) -> SResult<Option<Vec<DbValue>>> {
+        let Some(record) = self.read_archived::<T>(pk, snapshot).await? else {
+            return Ok(None);
+        };
+        let values = record.columns(columns)?;
+        Ok(Some(values))
+    }
+    ///Read a single typed column of a row, e.g. `transaction.project(pk, Person::project_name())`
+    pub async fn project<T, C>(&self, pk: Uuid, projection: Projection<T, C>) -> SResult<Option<C>>
+    where
+        T: RecordStruct,
+        C: TryFrom<DbValue, Error = ConvertError>,
+    {
+        self.read_projection(pk, projection, false).await
+    }
+    async fn read_projection<T, C>(
+        &self,
+        pk: Uuid,
+        projection: Projection<T, C>,
+        snapshot: bool,
+    ) -> SResult<Option<C>>
+    where
+        T: RecordStruct,
+        C: TryFrom<DbValue, Error = ConvertError>,
+    {
+        let Some(mut values) = self
+            .read_columns::<T>(pk, &[projection.id], snapshot)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let value = C::try_from(values.swap_remove(0))?;
+        Ok(Some(value))
+    }
+    ///Unconditionally write a row without reading it first
+    ///
+    /// Index entries of a previous version of the row are not removed, use [`Self::upsert`] when the row may exist
+    pub async fn put_value(&self, record: &impl RecordStruct, pk: Uuid) -> SResult<()> {
+        self.put_encoded(record, pk, &record.serialize()?).await
+    }
+    ///[`Self::put_value`] with the corpus already serialized by the caller
+    pub(super) async fn put_encoded<R: RecordStruct>(
+        &self,
+        record: &R,
+        pk: Uuid,
+        corpus: &[u8],
+    ) -> SResult<()> {
         let new_indices = record.indices(pk);
         for index in new_indices {
             self.set_index(index, pk)?
         }
-        self.set_corpus(pk, record)?;
+        self.write_corpus(pk, record, corpus).await?;
         Ok(())
     }
-    fn generate_index_key(&self, index: Key) -> Result<Vec<u8>, FdbBindingError> {
+    ///Change some columns of an existing row, only the indices of changed columns are rewritten
+    ///
+    /// ```ignore
+    /// transaction
+    ///     .update::<Person, _>(id, |patch| {
+    ///         patch.set_password(String::from("new password"));
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn update<T, F>(&self, pk: Uuid, apply: F) -> SResult<T>
+    where
+        T: Patchable,
+        F: FnOnce(&mut T::Patch<'_>),
+    {
+        let Some(mut record) = self.get_value::<T>(pk).await? else {
+            return Err(ExothermError::RowNotFound {
+                table: T::name(),
+                pk,
+            });
+        };
+        let old_indices = record.indices(pk);
+        let changed = {
+            let mut patch = record.patch();
+            apply(&mut patch);
+            T::changed(&patch)
+        };
+        if changed.is_empty() {
+            return Ok(record);
+        }
+        let touched =
+            |key: &Key| matches!(key.purpose, Purpose::Index(id, _) if changed.contains(&id));
+        let old_keys = old_indices.into_iter().filter(touched).collect();
+        let new_keys = record.indices(pk).into_iter().filter(touched).collect();
+        self.swap_indices(old_keys, new_keys, pk)?;
+        self.set_corpus(pk, &record).await?;
+        Ok(record)
+    }
+    ///Write a new row, fails with [`ExothermError::RowExists`] if the primary key is already taken
+    pub async fn insert<T: RecordStruct<Decoded = T>>(&self, record: &T, pk: Uuid) -> SResult<()> {
+        let key = T::corpus_key(self.tenant.clone(), pk).generate()?;
+        if self.trx.get(&key, false).await?.is_some() {
+            return Err(ExothermError::RowExists {
+                table: T::name(),
+                pk,
+            });
+        }
+        self.put_value(record, pk).await
+    }
+    ///Overwrite an existing row, fails with [`ExothermError::RowNotFound`] if there is none
+    pub async fn replace<T: RecordStruct<Decoded = T>>(&self, record: &T, pk: Uuid) -> SResult<()> {
+        let Some(old) = self.get_value::<T>(pk).await? else {
+            return Err(ExothermError::RowNotFound {
+                table: T::name(),
+                pk,
+            });
+        };
+        self.swap_indices(old.indices(pk), record.indices(pk), pk)?;
+        self.set_corpus(pk, record).await
+    }
+    ///Write a row whether or not it exists, index entries of a previous version are removed
+    ///
+    /// Returns whether a previous version was replaced
+    pub async fn upsert<T: RecordStruct<Decoded = T>>(
+        &self,
+        record: &T,
+        pk: Uuid,
+    ) -> SResult<bool> {
+        self.upsert_encoded(record, pk, &record.serialize()?).await
+    }
+    ///[`Self::upsert`] with the corpus already serialized by the caller
+    pub(super) async fn upsert_encoded<T: RecordStruct<Decoded = T>>(
+        &self,
+        record: &T,
+        pk: Uuid,
+        corpus: &[u8],
+    ) -> SResult<bool> {
+        let old = self.get_value::<T>(pk).await?;
+        let replaced = old.is_some();
+        let old_indices = old.map(|old| old.indices(pk)).unwrap_or_default();
+        self.swap_indices(old_indices, record.indices(pk), pk)?;
+        self.write_corpus(pk, record, corpus).await?;
+        Ok(replaced)
+    }
+    ///Run `body` at most once for the idempotency key `id`
+    ///
+    /// The outcome is stored under the key in the same commit. When the key is found, which is
+    /// the case after a `commit_unknown_result` that did commit ([`STransaction::maybe_commited`])
+    /// or when the caller retries with the same key, `body` is skipped and the stored outcome returned.
+    /// Keys are kept until [`STransaction::clear_idempotency_key`] is called.
+    /// ```ignore
+    ///     db.transact(|transaction| async move {
+    ///         transaction
+    ///             .idempotent(request_id, || async {
+    ///                 let mut counter = transaction.get_value::<Counter>(id).await?.unwrap_or_default();
+    ///                 counter.value += 1;
+    ///                 transaction.put_value(&counter, id).await?;
+    ///                 Ok(counter.value)
+    ///             })
+    ///             .await
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn idempotent<T, F, Fut>(&self, id: Uuid, body: F) -> SResult<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = SResult<T>>,
+    {
+        let key = Key::new_idempotency(self.tenant.clone(), id).generate()?;
+        if let Some(stored) = self.trx.get(&key, false).await? {
+            //The outcome follows the time it was stored at
+            let outcome = serde_json::from_slice(stored.get(8..).unwrap_or_default())?;
+            return Ok(outcome);
+        }
+        let outcome = body().await?;
+        self.writable()?;
+        let mut value = now_millis().to_le_bytes().to_vec();
+        value.extend_from_slice(&serde_json::to_vec(&outcome)?);
+        self.trx.set(&key, &value);
+        Ok(outcome)
+    }
+    ///Forget the outcome stored for an idempotency key
+    ///
+    /// Keys that are no longer needed can also be expired by age with
+    /// [`crate::database::database::Database::expire_idempotency_keys`].
+    pub fn clear_idempotency_key(&self, id: Uuid) -> SResult<()> {
+        self.writable()?;
+        let key = Key::new_idempotency(self.tenant.clone(), id).generate()?;
+        self.trx.clear(&key);
+        Ok(())
+    }
+    ///Clear every row and index entry of a table with range clears
+    ///
+    /// Row versions are kept, like for [`Self::clear_value`]. The size of the transaction does not depend on the size of the table.
+    /// Fails for tables with [`crate::database::blobstore::Blob`] columns, whose data would be left behind,
+    /// [`crate::database::database::Database::truncate_table`] releases it row by row first.
+    pub fn truncate_table<T: RecordStruct>(&self) -> SResult<()> {
+        if T::has_blobs() {
+            return Err(ExothermError::TableHasBlobs { table: T::name() });
+        }
+        self.clear_table::<T>()
+    }
+    pub(super) fn clear_table<T: RecordStruct>(&self) -> SResult<()> {
+        self.writable()?;
+        for tag in [Purpose::ROW_TAG, Purpose::INDEX_TAG] {
+            let range = Key::purpose_range(self.tenant.clone(), T::name(), tag)?;
+            self.trx.clear_range(&range.begin, &range.end);
+        }
+        if self.capture_changes {
+            self.log_truncate(self.tenant.clone(), T::name())?;
+        }
+        self.bump_changes(T::name())
+    }
+    ///Clear every key of a tenant, including versions and idempotency keys
+    pub fn clear_tenant(&self, tenant: Tenant) -> SResult<()> {
+        self.writable()?;
+        let range = KeyRange::prefix(Key::tenant_prefix(tenant.clone())?);
+        self.trx.clear_range(&range.begin, &range.end);
+        if self.capture_changes {
+            //Written after the clear, so the entry is the first one of the emptied log
+            self.log_truncate(tenant, "")?;
+        }
+        Ok(())
+    }
+    ///Delete up to `limit` rows whose index entries are in `begin..end`
+    ///
+    /// Returns the number of deleted rows and where to continue if the limit was reached.
+    /// Index entries that point to a missing row or to a row that no longer has the indexed value are cleared without deleting the row.
+    pub(crate) async fn delete_index_range<T: RecordStruct<Decoded = T>>(
+        &self,
+        begin: &[u8],
+        end: &[u8],
+        limit: usize,
+    ) -> SResult<(u64, Option<Vec<u8>>)> {
+        self.writable()?;
+        let mut opt = RangeOption::from((begin, end));
+        opt.limit = Some(limit);
+        opt.mode = StreamingMode::WantAll;
+        let range = self.trx.get_range(&opt, 1, false).await?;
+        let mut deleted = 0;
+        for kv in &range {
+            let pk = Uuid::from_slice(kv.value())?;
+            let row = self.get_value::<T>(pk).await?;
+            let matches = match &row {
+                Some(row) => row
+                    .indices(pk)
+                    .into_iter()
+                    .map(|key| self.scoped_key(key))
+                    .collect::<SResult<Vec<_>>>()?
+                    .iter()
+                    .any(|key| key.as_slice() == kv.key()),
+                None => false,
+            };
+            match row {
+                Some(row) if matches => {
+                    for index in row.indices(pk) {
+                        self.clear_index(index)?;
+                    }
+                    self.clear_corpus(pk, &row).await?;
+                    deleted += 1;
+                }
+                _ => self.trx.clear(kv.key()),
+            }
+        }
+        let next = match range.last() {
+            Some(last) if range.more() => {
+                let mut next = last.key().to_vec();
+                next.push(0);
+                Some(next)
+            }
+            _ => None,
+        };
+        Ok((deleted, next))
+    }
+    ///Future that resolves once the row is written or cleared by another transaction
+    ///
+    /// The watch only starts when this transaction commits, so await it after the transaction finished.
+    /// ```ignore
+    ///     let changed = db
+    ///         .transact(|transaction| async move { transaction.watch_value::<Person>(id) })
+    ///         .await?;
+    ///     changed.await?;
+    /// ```
+    pub fn watch_value<T: RecordStruct>(
+        &self,
+        pk: Uuid,
+    ) -> SResult<impl Future<Output = SResult<()>> + Send + use<T>> {
+        let key = Key::new_version(self.tenant.clone(), T::name(), pk).generate()?;
+        Ok(watch(self.trx.watch(&key)))
+    }
+    ///Future that resolves once any row of the table is written or cleared
+    ///
+    /// Only writes made with [`crate::database::options::TransactOptions::notify_subscribers`] resolve it.
+    pub fn watch_table<T: RecordStruct>(
+        &self,
+    ) -> SResult<impl Future<Output = SResult<()>> + Send + use<T>> {
+        let key = Key::new_changes(self.tenant.clone(), T::name()).generate()?;
+        Ok(watch(self.trx.watch(&key)))
+    }
+    ///Row ids of up to `limit` index entries in `begin..end`
+    ///
+    /// Returns where to continue if the range has more entries.
+    pub(crate) async fn index_ids(
+        &self,
+        begin: &[u8],
+        end: &[u8],
+        limit: usize,
+    ) -> SResult<(Vec<Uuid>, Option<Vec<u8>>)> {
+        let mut opt = RangeOption::from((begin, end));
+        opt.limit = Some(limit);
+        opt.mode = StreamingMode::WantAll;
+        let page = self.trx.get_range(&opt, 1, false).await?;
+        let mut ids = Vec::with_capacity(page.len());
+        for kv in &page {
+            ids.push(Uuid::from_slice(kv.value())?);
+        }
+        let next = match page.last() {
+            Some(last) if page.more() => {
+                let mut next = last.key().to_vec();
+                next.push(0);
+                Some(next)
+            }
+            _ => None,
+        };
+        Ok((ids, next))
+    }
+    ///Clear index entries that are only in `old` and set the ones that are only in `new`
+    fn swap_indices(&self, old: Vec<Key>, new: Vec<Key>, pk: Uuid) -> SResult<()> {
+        self.writable()?;
+        let old = old
+            .into_iter()
+            .map(|key| self.scoped_key(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let new = new
+            .into_iter()
+            .map(|key| self.scoped_key(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        for key in &old {
+            if !new.contains(key) {
+                self.trx.clear(key);
+            }
+        }
+        for key in &new {
+            if !old.contains(key) {
+                self.trx.set(key, pk.as_bytes());
+            }
+        }
+        Ok(())
+    }
+    ///Generate a key in the tenant of the transaction
+    fn scoped_key(&self, index: Key) -> SResult<Vec<u8>> {
         let mut key = index;
-        key.tenant = self.tenant;
-        let key = key
-            .generate()
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
+        key.tenant = self.tenant.clone();
+        let key = key.generate()?;
         Ok(key)
     }
-    fn set_index(&self, index: Key, pk: Uuid) -> Result<(), FdbBindingError> {
+    fn set_index(&self, index: Key, pk: Uuid) -> SResult<()> {
+        self.writable()?;
         let value = pk.as_bytes();
-        let key = self.generate_index_key(index)?;
+        let key = self.scoped_key(index)?;
         //println!("{}{:?}", self.tenant, index.into_key());
-        println!("Index {}->{value:?}", String::from_utf8_lossy(&key));
+        //println!("Index {}->{value:?}", String::from_utf8_lossy(&key));
         self.trx.set(&key, value);
         Ok(())
     }
-    fn clear_index(&self, index: Key) -> Result<(), FdbBindingError> {
-        let key = self.generate_index_key(index)?;
+    fn clear_index(&self, index: Key) -> SResult<()> {
+        self.writable()?;
+        let key = self.scoped_key(index)?;
         //println!("{}{:?}", self.tenant, index.into_key());
         //println!("Index {}->{value:?}", String::from_utf8_lossy(&key));
         self.trx.clear(&key);
         Ok(())
     }
-    pub async fn query_index(
+    ///Scan an index, the scanned range conflicts with concurrent writes to it
+    pub async fn query_index(&self, query: Query, reverse: bool) -> SResult<PageResult<'_>> {
+        self.read_index(query, reverse, false).await
+    }
+    async fn read_index(
         &self,
         query: Query,
         reverse: bool,
-    ) -> Result<PageResult, FdbBindingError> {
-        let Range(from, to) = query
-            .into_range(self.tenant)
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
-        let from = from
-            .generate()
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
-        let to = to
-            .generate()
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
+        snapshot: bool,
+    ) -> SResult<PageResult<'_>> {
+        let Range(from, to) = query.into_range(self.tenant.clone())?;
+        let from = from.generate()?;
+        let to = to.generate()?;
         let mut opt = RangeOption::from((from, to));
         opt.mode = StreamingMode::Iterator;
         opt.reverse = reverse;
-        let range = self.trx.get_range(&opt, 5000, true).await?;
+        let range = self.trx.get_range(&opt, 5000, snapshot).await?;
         let mut used_bandwidth: usize = 0;
         let mut ids = Vec::<Uuid>::new();
         for kv in &range {
             used_bandwidth += kv.key().len();
             used_bandwidth += kv.value().len();
-            let record_id = Uuid::from_slice(kv.value())
-                .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
+            let record_id = Uuid::from_slice(kv.value())?;
             ids.push(record_id);
         }
         let next = opt.next_range(&range);
@@ -234,24 +743,103 @@ impl STransaction {
         };
         Ok(page)
     }
-    fn clear_corpus(&self, pk: Uuid, record: &impl RecordStruct) -> Result<(), FdbBindingError> {
-        let crp_key = record
-            .get_corpus_key(self.tenant, pk)
-            .generate()
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
+    async fn clear_corpus(&self, pk: Uuid, record: &impl RecordStruct) -> SResult<()> {
+        self.writable()?;
+        self.release_blobs(&record.blobs()).await?;
+        let crp_key = record.get_corpus_key(self.tenant.clone(), pk).generate()?;
         //let crp_key = self.corpus_key(pk, record);
+        if self.capture_changes {
+            let old = self.trx.get(&crp_key, false).await?;
+            self.log_change(record.tname(), pk, ChangeOp::Clear, old.as_deref(), None)?;
+        }
         self.trx.clear(&crp_key);
+        self.bump_version(pk, record)
+    }
+    ///Every write goes through a helper that checks this first
+    pub(super) fn writable(&self) -> SResult<()> {
+        if self.read_only {
+            Err(ExothermError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+    fn bump_version(&self, pk: Uuid, record: &impl RecordStruct) -> SResult<()> {
+        let key = Key::new_version(self.tenant.clone(), record.tname(), pk).generate()?;
+        self.trx
+            .atomic_op(&key, &1u64.to_le_bytes(), MutationType::Add);
+        self.bump_changes(record.tname())
+    }
+    ///Write `value` under `prefix`, followed by the commit versionstamp and a counter, so entries sort in commit order
+    ///
+    /// Fails once the counter is exhausted, a transaction can write at most `u16::MAX` such keys.
+    pub(super) fn set_versionstamped(&self, prefix: Vec<u8>, value: &[u8]) -> SResult<()> {
+        let order = self.next_stamp_order()?;
+        self.set_versionstamped_at(prefix, order, &[], value);
         Ok(())
     }
-    fn set_corpus(&self, pk: Uuid, record: &impl RecordStruct) -> Result<(), FdbBindingError> {
-        let crp_value: rkyv::util::AlignedVec<16> = record
-            .serialize()
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
-        let crp_key = record
-            .get_corpus_key(self.tenant, pk)
-            .generate()
-            .map_err(|e| FdbBindingError::new_custom_error(Box::new(e)))?;
-        self.trx.set(&crp_key, &crp_value);
+    ///Reserve the counter of the next versionstamped entry, its keys share it
+    pub(super) fn next_stamp_order(&self) -> SResult<u16> {
+        self.stamp_order
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |order| {
+                order.checked_add(1)
+            })
+            .map_err(|_| ExothermError::TooManyVersionstamps)
+    }
+    ///Write one key of an entry, `suffix` follows the versionstamp and the counter
+    pub(super) fn set_versionstamped_at(
+        &self,
+        prefix: Vec<u8>,
+        order: u16,
+        suffix: &[u8],
+        value: &[u8],
+    ) {
+        let mut key = prefix;
+        let offset = key.len() as u32;
+        key.extend_from_slice(&[0xff; 10]);
+        key.extend_from_slice(&order.to_be_bytes());
+        key.extend_from_slice(suffix);
+        key.extend_from_slice(&offset.to_le_bytes());
+        self.trx
+            .atomic_op(&key, value, MutationType::SetVersionstampedKey);
+    }
+    ///Atomic adds do not conflict, so every writer of a table can bump the same counter
+    ///
+    /// The counter is a single key, so all writes to a table land on one storage server.
+    /// Only transactions with [`crate::database::options::TransactOptions::notify_subscribers`] pay for it.
+    fn bump_changes(&self, table: &'static str) -> SResult<()> {
+        if !self.notify_subscribers {
+            return Ok(());
+        }
+        let key = Key::new_changes(self.tenant.clone(), table).generate()?;
+        self.trx
+            .atomic_op(&key, &1u64.to_le_bytes(), MutationType::Add);
         Ok(())
     }
+    async fn set_corpus<R: RecordStruct>(&self, pk: Uuid, record: &R) -> SResult<()> {
+        let crp_value: rkyv::util::AlignedVec<16> = record.serialize()?;
+        self.write_corpus(pk, record, &crp_value).await
+    }
+    async fn write_corpus<R: RecordStruct>(
+        &self,
+        pk: Uuid,
+        record: &R,
+        crp_value: &[u8],
+    ) -> SResult<()> {
+        self.writable()?;
+        let crp_key = record.get_corpus_key(self.tenant.clone(), pk).generate()?;
+        let old = if self.capture_changes || R::has_blobs() {
+            self.trx.get(&crp_key, false).await?
+        } else {
+            None
+        };
+        if R::has_blobs() {
+            self.swap_blobs(old.as_deref(), &record.blobs()).await?;
+        }
+        if self.capture_changes {
+            let (table, new) = (record.tname(), Some(crp_value));
+            self.log_change(table, pk, ChangeOp::Put, old.as_deref(), new)?;
+        }
+        self.trx.set(&crp_key, crp_value);
+        self.bump_version(pk, record)
+    }
 }
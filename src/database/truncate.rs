@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use foundationdb::{KeySelector, RangeOption, options::StreamingMode};
+
+use crate::{
+    database::{
+        blobstore::decode_count,
+        database::{Database, now_millis},
+        key::{Key, KeyRange, Purpose, Tenant},
+        record::RecordStruct,
+        transaction::Query,
+    },
+    error::SResult,
+};
+
+///Number of keys a range delete affects, as counted by a dry run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyCount {
+    pub rows: u64,
+    pub index_entries: u64,
+    pub blob_shards: u64,
+    ///Versions, idempotency keys and anything else
+    pub other: u64,
+}
+
+impl KeyCount {
+    pub fn total(&self) -> u64 {
+        self.rows + self.index_entries + self.blob_shards + self.other
+    }
+    ///Count a key by the purpose byte that follows the table name
+    fn add(&mut self, key: &[u8], tenant_prefix: usize) {
+        let tag = key
+            .get(tenant_prefix..)
+            .and_then(|rest| rest.iter().position(|b| *b == 0))
+            .and_then(|end| key.get(tenant_prefix + end + 1));
+        match tag {
+            Some(&Purpose::ROW_TAG) => self.rows += 1,
+            Some(&Purpose::INDEX_TAG) => self.index_entries += 1,
+            Some(&Purpose::BLOB_TAG) => self.blob_shards += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+///Keys read per transaction while counting
+const COUNT_PAGE: usize = 10_000;
+///Rows deleted per transaction by [`Database::delete_where`]
+const DELETE_BATCH: usize = 1_000;
+///Idempotency keys checked per transaction by [`Database::expire_idempotency_keys`]
+const EXPIRE_PAGE: usize = 1_000;
+
+impl Database {
+    ///Delete every row and index entry of a table
+    ///
+    /// The data of [`crate::database::blobstore::Blob`] columns is released in batches of rows first,
+    /// the rest is cleared in one transaction once no rows with blobs are left. Blobs stored with
+    /// `put_blob` belong to the tenant and are kept, use [`Self::drop_tenant`] to remove them as well.
+    pub async fn truncate_table<T: RecordStruct>(&self) -> SResult<()> {
+        loop {
+            let done = self
+                .transact(|transaction| async move {
+                    if T::has_blobs() && transaction.release_table_blobs::<T>(DELETE_BATCH).await? {
+                        return Ok(false);
+                    }
+                    transaction.clear_table::<T>()?;
+                    Ok(true)
+                })
+                .await?;
+            if done {
+                return Ok(());
+            }
+        }
+    }
+    ///Count what [`Self::truncate_table`] would delete, without deleting anything
+    ///
+    /// Shards of blobs that rows reference in [`crate::database::blobstore::Blob`] columns are not counted.
+    pub async fn truncate_table_dry_run<T: RecordStruct>(&self) -> SResult<KeyCount> {
+        let mut count = KeyCount::default();
+        for tag in [Purpose::ROW_TAG, Purpose::INDEX_TAG] {
+            let range = Key::purpose_range(self.tenant(), T::name(), tag)?;
+            self.count_range(&range, self.tenant(), &mut count).await?;
+        }
+        Ok(count)
+    }
+    ///Delete all data of a tenant
+    pub async fn drop_tenant(&self, tenant: Tenant) -> SResult<()> {
+        self.transact_with_tenant(tenant.clone(), |transaction| {
+            let tenant = &tenant;
+            async move { transaction.clear_tenant(tenant.clone()) }
+        })
+        .await
+    }
+    ///Count what [`Self::drop_tenant`] would delete, without deleting anything
+    pub async fn drop_tenant_dry_run(&self, tenant: Tenant) -> SResult<KeyCount> {
+        let mut count = KeyCount::default();
+        let range = KeyRange::prefix(Key::tenant_prefix(tenant.clone())?);
+        self.count_range(&range, tenant, &mut count).await?;
+        Ok(count)
+    }
+    ///Delete every row an index query matches, together with all of its index entries
+    ///
+    /// Large results are deleted in several transactions, so the delete is not atomic as a whole.
+    /// Returns the number of deleted rows.
+    /// ```ignore
+    ///     let eq = Person::name_index(Uuid::nil(), &String::from("Name"));
+    ///     let deleted = db.delete_where::<Person>(Query::Equal(eq)).await?;
+    /// ```
+    pub async fn delete_where<T: RecordStruct<Decoded = T>>(&self, query: Query) -> SResult<u64> {
+        let range = query.key_range(self.tenant())?;
+        let mut begin = range.begin;
+        let mut deleted = 0;
+        loop {
+            let (batch, next) = self
+                .transact(|transaction| {
+                    let (begin, end) = (&begin, &range.end);
+                    async move {
+                        transaction
+                            .delete_index_range::<T>(begin, end, DELETE_BATCH)
+                            .await
+                    }
+                })
+                .await?;
+            deleted += batch;
+            match next {
+                Some(next) => begin = next,
+                None => return Ok(deleted),
+            }
+        }
+    }
+    ///Delete the idempotency keys of the tenant that were stored more than `older_than` ago
+    ///
+    /// Returns the number of deleted keys. A retry that arrives after its key was deleted runs
+    /// its body again, so keep keys longer than clients retry.
+    /// ```ignore
+    ///     db.expire_idempotency_keys(Duration::from_secs(24 * 60 * 60)).await?;
+    /// ```
+    pub async fn expire_idempotency_keys(&self, older_than: Duration) -> SResult<usize> {
+        let deadline = now_millis().saturating_sub(older_than.as_millis() as u64);
+        let range = Key::purpose_range(self.tenant(), "", Purpose::IDEMPOTENCY_TAG)?;
+        let mut begin = range.begin.clone();
+        let mut expired = 0;
+        loop {
+            let (page, next) = self
+                .transact(|transaction| {
+                    let (begin, end) = (begin.as_slice(), range.end.as_slice());
+                    async move {
+                        transaction.writable()?;
+                        let opt = RangeOption {
+                            limit: Some(EXPIRE_PAGE),
+                            mode: StreamingMode::WantAll,
+                            ..RangeOption::from((begin, end))
+                        };
+                        let values = transaction.trx.get_range(&opt, 1, false).await?;
+                        let mut expired = 0;
+                        for kv in &values {
+                            let stored_at = decode_count(kv.value());
+                            if stored_at <= deadline {
+                                transaction.trx.clear(kv.key());
+                                expired += 1;
+                            }
+                        }
+                        let next = match values.last() {
+                            Some(last) if values.more() => {
+                                let mut next = last.key().to_vec();
+                                next.push(0);
+                                Some(next)
+                            }
+                            _ => None,
+                        };
+                        Ok((expired, next))
+                    }
+                })
+                .await?;
+            expired += page;
+            match next {
+                Some(next) => begin = next,
+                None => return Ok(expired),
+            }
+        }
+    }
+    ///Page through a range with snapshot reads, one transaction per page so large ranges do not time out
+    async fn count_range(
+        &self,
+        range: &KeyRange,
+        tenant: Tenant,
+        count: &mut KeyCount,
+    ) -> SResult<()> {
+        let tenant_prefix = Key::tenant_prefix(tenant.clone())?.len();
+        let mut begin = range.begin.clone();
+        loop {
+            let (page, last) = self
+                .transact_with_tenant(tenant.clone(), |transaction| {
+                    let begin = &begin;
+                    async move {
+                        let opt = RangeOption {
+                            begin: KeySelector::first_greater_or_equal(begin.as_slice()),
+                            end: KeySelector::first_greater_or_equal(range.end.as_slice()),
+                            limit: Some(COUNT_PAGE),
+                            mode: StreamingMode::WantAll,
+                            ..RangeOption::default()
+                        };
+                        let values = transaction.trx.get_range(&opt, 1, true).await?;
+                        let mut page = KeyCount::default();
+                        for kv in &values {
+                            page.add(kv.key(), tenant_prefix);
+                        }
+                        let last = values
+                            .more()
+                            .then(|| values.last().map(|kv| kv.key().to_vec()))
+                            .flatten();
+                        Ok((page, last))
+                    }
+                })
+                .await?;
+            count.rows += page.rows;
+            count.index_entries += page.index_entries;
+            count.blob_shards += page.blob_shards;
+            count.other += page.other;
+            let Some(mut last) = last else {
+                return Ok(());
+            };
+            last.push(0);
+            begin = last;
+        }
+    }
+}
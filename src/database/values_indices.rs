@@ -1,7 +1,7 @@
-use std::i32;
-
 use uuid::Uuid;
 
+use crate::database::{blobstore::Blob, content::ContentId, record::BlobstoreAddress};
+
 #[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone)]
 pub enum DbValue {
     Bool(bool),
@@ -18,6 +18,9 @@ pub enum DbValue {
     Blob(Vec<u8>),
     Uuid(Uuid),
     None,
+    //Appended so that the discriminants of stored rows stay the same
+    BlobAddress(BlobstoreAddress),
+    ContentAddress(BlobstoreAddress, ContentId),
 }
 #[derive(rkyv::Serialize, rkyv::Deserialize, rkyv::Archive, Debug, Clone)]
 pub struct Row(pub Vec<DbValue>);
@@ -29,9 +32,15 @@ pub trait IndexExtractable {
 }
 
 pub trait DbValueEncode {
+    ///Whether the column keeps its data in the blobstore
+    const BLOB: bool = false;
     fn encode_db(&self) -> DbValue {
         DbValue::None
     }
+    ///The value if it is a [`Blob`]
+    fn blob(&self) -> Option<&Blob> {
+        None
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -145,6 +154,7 @@ impl<T> DbValueEncode for Option<T>
 where
     T: DbValueEncode,
 {
+    const BLOB: bool = T::BLOB;
     fn encode_db(&self) -> DbValue {
         if let Some(some) = self {
             some.encode_db()
@@ -152,6 +162,9 @@ where
             DbValue::None
         }
     }
+    fn blob(&self) -> Option<&Blob> {
+        self.as_ref().and_then(T::blob)
+    }
 }
 
 macro_rules! impl_db_value_encode {
@@ -0,0 +1,239 @@
+use std::marker::PhantomData;
+
+use foundationdb::future::FdbSlice;
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        blobstore::Blob,
+        record::ArchivedBlobstoreAddress,
+        values_indices::{ArchivedDbValue, ArchivedRow, DbValue},
+    },
+    error::{ConvertError, SResult},
+};
+
+///Archived row bytes as read from the database, which can be viewed without deserializing them
+///
+/// The bytes returned by FoundationDB are used in place if they happen to be aligned, otherwise they are copied once
+pub struct ArchivedRecord(Storage);
+
+enum Storage {
+    Fdb(FdbSlice),
+    Aligned(rkyv::util::AlignedVec<16>),
+}
+
+impl ArchivedRecord {
+    ///Copy serialized row bytes into an aligned buffer
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut aligned = rkyv::util::AlignedVec::<16>::with_capacity(bytes.len());
+        aligned.extend_from_slice(bytes);
+        ArchivedRecord(Storage::Aligned(aligned))
+    }
+    pub(crate) fn from_fdb(slice: FdbSlice) -> Self {
+        if slice.as_ptr().align_offset(16) == 0 {
+            ArchivedRecord(Storage::Fdb(slice))
+        } else {
+            Self::new(&slice)
+        }
+    }
+    pub fn bytes(&self) -> &[u8] {
+        match &self.0 {
+            Storage::Fdb(slice) => slice,
+            Storage::Aligned(aligned) => aligned,
+        }
+    }
+    ///Validate and access the archived row
+    pub fn row(&self) -> SResult<&ArchivedRow> {
+        let row = rkyv::access::<ArchivedRow, rkyv::rancor::Error>(self.bytes())?;
+        Ok(row)
+    }
+    ///Borrow the row as a view struct generated by schema! or the Record derive
+    pub fn view<'a, V: RecordView<'a>>(&'a self) -> SResult<V> {
+        let view = V::from_archived(self.row()?)?;
+        Ok(view)
+    }
+    ///Borrow a single column without touching the others
+    pub fn column<C: ColumnView>(&self, id: u16) -> SResult<C::View<'_>> {
+        let row = self.row()?;
+        let view = C::view(row.0.get(id as usize))?;
+        Ok(view)
+    }
+    ///Deserialize only the requested columns, in the order they are requested
+    ///
+    /// Columns the row does not have are returned as [`DbValue::None`]
+    pub fn columns(&self, ids: &[u16]) -> SResult<Vec<DbValue>> {
+        let row = self.row()?;
+        let mut values = Vec::with_capacity(ids.len());
+        for id in ids {
+            let value = match row.0.get(*id as usize) {
+                Some(value) => rkyv::deserialize::<DbValue, rkyv::rancor::Error>(value)?,
+                None => DbValue::None,
+            };
+            values.push(value);
+        }
+        Ok(values)
+    }
+    ///Read the column of a projection generated as `Table::project_<field>()`
+    pub fn project<T, C: ColumnView>(&self, projection: Projection<T, C>) -> SResult<C::View<'_>> {
+        self.column::<C>(projection.id)
+    }
+}
+
+///Typed handle to a single column `C` of the table `T`, generated as `T::project_<field>()`
+pub struct Projection<T, C> {
+    pub id: u16,
+    _marker: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, C> Projection<T, C> {
+    pub const fn new(id: u16) -> Self {
+        Projection {
+            id,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, C> Clone for Projection<T, C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T, C> Copy for Projection<T, C> {}
+
+///Borrowed view over an archived row, generated next to a schema
+pub trait RecordView<'a>: Sized {
+    fn from_archived(row: &'a ArchivedRow) -> Result<Self, ConvertError>;
+}
+
+///Maps a column type to the type it can be borrowed as from an archived row
+pub trait ColumnView {
+    type View<'a>;
+    ///`None` means that the row has no value stored for the column
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError>;
+}
+
+fn mismatch(value: Option<&ArchivedDbValue>) -> ConvertError {
+    match value {
+        Some(value) => ConvertError::CantConvert {
+            from: rkyv::deserialize::<DbValue, rkyv::rancor::Error>(value).unwrap_or(DbValue::None),
+        },
+        None => ConvertError::MissingColumn,
+    }
+}
+
+impl ColumnView for String {
+    type View<'a> = &'a str;
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+        match value {
+            Some(ArchivedDbValue::String(value)) => Ok(value.as_str()),
+            _ => Err(mismatch(value)),
+        }
+    }
+}
+
+impl ColumnView for Option<String> {
+    type View<'a> = Option<&'a str>;
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+        match value {
+            Some(ArchivedDbValue::String(value)) => Ok(Some(value.as_str())),
+            Some(ArchivedDbValue::None) | None => Ok(None),
+            _ => Err(mismatch(value)),
+        }
+    }
+}
+
+impl ColumnView for Vec<u8> {
+    type View<'a> = &'a [u8];
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+        match value {
+            Some(ArchivedDbValue::Blob(value)) => Ok(value.as_slice()),
+            _ => Err(mismatch(value)),
+        }
+    }
+}
+
+impl ColumnView for Blob {
+    type View<'a> = &'a ArchivedBlobstoreAddress;
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+        match value {
+            Some(
+                ArchivedDbValue::BlobAddress(address) | ArchivedDbValue::ContentAddress(address, _),
+            ) => Ok(address),
+            _ => Err(mismatch(value)),
+        }
+    }
+}
+
+impl ColumnView for Option<Blob> {
+    type View<'a> = Option<&'a ArchivedBlobstoreAddress>;
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+        match value {
+            Some(
+                ArchivedDbValue::BlobAddress(address) | ArchivedDbValue::ContentAddress(address, _),
+            ) => Ok(Some(address)),
+            Some(ArchivedDbValue::None) | None => Ok(None),
+            _ => Err(mismatch(value)),
+        }
+    }
+}
+
+impl ColumnView for Vec<f32> {
+    type View<'a> = &'a [rkyv::Archived<f32>];
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+        match value {
+            Some(ArchivedDbValue::Vector(value)) => Ok(value.as_slice()),
+            _ => Err(mismatch(value)),
+        }
+    }
+}
+
+impl ColumnView for Uuid {
+    type View<'a> = Uuid;
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+        match value {
+            Some(ArchivedDbValue::Uuid(value)) => Ok(*value),
+            _ => Err(mismatch(value)),
+        }
+    }
+}
+
+impl ColumnView for bool {
+    type View<'a> = bool;
+    fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+        match value {
+            Some(ArchivedDbValue::Bool(value)) => Ok(*value),
+            _ => Err(mismatch(value)),
+        }
+    }
+}
+
+macro_rules! impl_column_view {
+    ($type:ty, $variant:ident) => {
+        impl ColumnView for $type {
+            type View<'a> = $type;
+            fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+                match value {
+                    Some(ArchivedDbValue::$variant(value)) => Ok(value.to_native()),
+                    _ => Err(mismatch(value)),
+                }
+            }
+        }
+        impl ColumnView for Option<$type> {
+            type View<'a> = Option<$type>;
+            fn view(value: Option<&ArchivedDbValue>) -> Result<Self::View<'_>, ConvertError> {
+                match value {
+                    Some(ArchivedDbValue::$variant(value)) => Ok(Some(value.to_native())),
+                    Some(ArchivedDbValue::None) | None => Ok(None),
+                    _ => Err(mismatch(value)),
+                }
+            }
+        }
+    };
+}
+impl_column_view!(u32, Uint32);
+impl_column_view!(u64, Uint64);
+impl_column_view!(i32, Int32);
+impl_column_view!(i64, Int64);
+impl_column_view!(f32, Float);
+impl_column_view!(f64, Double);
@@ -0,0 +1,116 @@
+use std::{collections::HashSet, marker::PhantomData};
+
+use uuid::Uuid;
+
+use crate::{
+    database::{database::Database, key::KeyRange, record::RecordStruct, transaction::Query},
+    error::SResult,
+};
+
+///Rows that entered or left the result of an index query
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexDiff {
+    pub added: Vec<Uuid>,
+    pub removed: Vec<Uuid>,
+}
+
+impl IndexDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+///Rows read per transaction when a subscription runs its query
+const SUBSCRIPTION_PAGE: usize = 10_000;
+
+///Live result of an index query, created by [`Database::subscribe`]
+///
+/// The query is run again whenever a row of the table is written, changes to rows that stay
+/// in the result are not reported. Results larger than a page are read in several transactions,
+/// a write during the read only shows up in the next diff. Only writes made with
+/// [`crate::database::options::TransactOptions::notify_subscribers`] wake the subscription.
+pub struct IndexSubscription<'a, T> {
+    db: &'a Database,
+    range: KeyRange,
+    ids: HashSet<Uuid>,
+    _table: PhantomData<fn() -> T>,
+}
+
+impl<T: RecordStruct> IndexSubscription<'_, T> {
+    ///Wait for the next change of the result, the first call returns the whole result as added
+    pub async fn next(&mut self) -> SResult<IndexDiff> {
+        loop {
+            let mut ids = HashSet::new();
+            let mut begin = self.range.begin.clone();
+            let mut changed = None;
+            loop {
+                let (page, next, watch) = self
+                    .db
+                    .transact(|transaction| {
+                        let (begin, end) = (&begin, &self.range.end);
+                        let first = changed.is_none();
+                        async move {
+                            let (page, next) =
+                                transaction.index_ids(begin, end, SUBSCRIPTION_PAGE).await?;
+                            //Watch from the first page on, so writes to later pages are not missed
+                            let watch = if first {
+                                Some(transaction.watch_table::<T>()?)
+                            } else {
+                                None
+                            };
+                            Ok((page, next, watch))
+                        }
+                    })
+                    .await?;
+                ids.extend(page);
+                changed = changed.or(watch);
+                match next {
+                    Some(next) => begin = next,
+                    None => break,
+                }
+            }
+            let diff = IndexDiff {
+                added: ids.difference(&self.ids).copied().collect(),
+                removed: self.ids.difference(&ids).copied().collect(),
+            };
+            self.ids = ids;
+            if !diff.is_empty() {
+                return Ok(diff);
+            }
+            if let Some(changed) = changed {
+                changed.await?;
+            }
+        }
+    }
+    ///Rows currently in the result
+    pub fn ids(&self) -> &HashSet<Uuid> {
+        &self.ids
+    }
+}
+
+impl Database {
+    ///Wait until a row is written or cleared
+    pub async fn watch_value<T: RecordStruct>(&self, pk: Uuid) -> SResult<()> {
+        let changed = self
+            .transact(|transaction| async move { transaction.watch_value::<T>(pk) })
+            .await?;
+        changed.await
+    }
+    ///Follow the result of an index query
+    /// ```ignore
+    ///     let eq = Person::name_index(Uuid::nil(), &String::from("Name"));
+    ///     let mut subscription = db.subscribe::<Person>(Query::Equal(eq))?;
+    ///     loop {
+    ///         let diff = subscription.next().await?;
+    ///         push_to_clients(diff.added, diff.removed);
+    ///     }
+    /// ```
+    pub fn subscribe<T: RecordStruct>(&self, query: Query) -> SResult<IndexSubscription<'_, T>> {
+        Ok(IndexSubscription {
+            db: self,
+            range: query.key_range(self.tenant())?,
+            ids: HashSet::new(),
+            _table: PhantomData,
+        })
+    }
+}
@@ -33,6 +33,56 @@ pub enum ExothermError {
     IndexKeyError,
     #[error("Cant set an index between different columns")]
     UnequalColumns,
+    #[error("Row {pk} already exists in table {table}")]
+    RowExists { table: &'static str, pk: uuid::Uuid },
+    #[error("No row {pk} in table {table}")]
+    RowNotFound { table: &'static str, pk: uuid::Uuid },
+    #[error("Row {pk} in table {table} is at version {found}, expected {expected}")]
+    VersionConflict {
+        table: &'static str,
+        pk: uuid::Uuid,
+        expected: u64,
+        found: u64,
+    },
+    #[error("Table {table} of {new} is already registered by {existing}")]
+    DuplicateTable {
+        table: &'static str,
+        existing: &'static str,
+        new: &'static str,
+    },
+    #[error("Bucket name {0:?} must not be empty or contain NUL bytes")]
+    InvalidBucket(String),
+    #[error("Tenant name {0:?} must not be empty or contain NUL bytes")]
+    InvalidTenant(String),
+    #[error("Blob of {size} bytes is larger than the maximum of {max} bytes")]
+    BlobTooLarge { size: u64, max: u64 },
+    #[error("Blob {id} in bucket {bucket} was replaced while it was being read")]
+    BlobChanged { bucket: String, id: uuid::Uuid },
+    #[error(
+        "Blob {id} in bucket {bucket} is corrupted, shard {shard:?} does not match its checksum"
+    )]
+    BlobCorrupted {
+        bucket: String,
+        id: uuid::Uuid,
+        ///`None` if only the checksum of the whole blob does not match
+        shard: Option<u32>,
+    },
+    #[error("Upload of blob {id} in bucket {bucket} was collected as abandoned")]
+    UploadAbandoned { bucket: String, id: uuid::Uuid },
+    #[error("Content {content} collides with other content in bucket {bucket}")]
+    ContentCollision { bucket: String, content: String },
+    #[error("A transaction can write at most 65535 change log entries and outbox events")]
+    TooManyVersionstamps,
+    #[error("Table {table} has blob columns, truncate it with Database::truncate_table")]
+    TableHasBlobs { table: &'static str },
+    #[error("Range is outside of the blob of {size} bytes")]
+    RangeNotSatisfiable { size: u64 },
+    #[error("Can not write in a read only transaction")]
+    ReadOnly,
+    #[error("Outbox lease of worker {worker} was taken over before the batch was acknowledged")]
+    LeaseLost { worker: uuid::Uuid },
+    #[error("Outbox sink failed: {0}")]
+    Sink(String),
     //#[error("{0}")]
     //Lance(#[from] lancedb::Error),
 }
@@ -41,4 +91,6 @@ pub enum ExothermError {
 pub enum ConvertError {
     #[error("CantConvert from {from:?}")]
     CantConvert { from: DbValue },
+    #[error("The row has no value for a required column")]
+    MissingColumn,
 }
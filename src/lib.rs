@@ -1,6 +1,11 @@
+extern crate self as exotherm;
+
 pub mod database;
 pub mod error;
 
+pub use exotherm_derive::Record;
+pub use uuid;
+
 #[cfg(test)]
 mod tests {
     use uuid::Uuid;
@@ -13,10 +18,207 @@ mod tests {
         1 -> password:[] String,
     });
 
+    schema!(Member in "auth" as "people" {
+        0 -> name: [] String,
+    });
+
+    schema!(Document {
+        0 -> title: [] String,
+        1 -> body: [] database::blobstore::Blob,
+    });
+
+    #[derive(Debug, Clone, PartialEq, Record)]
+    #[exo(namespace = "billing", view = "AccountRef")]
+    pub struct Account {
+        #[exo(id = 0, index = "email_index")]
+        email: String,
+        #[exo(id = 2)]
+        logins: Option<u64>,
+    }
+
+    #[test]
+    fn derived_roundtrip() -> SResult<()> {
+        use database::record::{Patchable, RecordStruct};
+        let account = Account {
+            email: String::from("someone@example.com"),
+            logins: Some(3),
+        };
+        let bytes = account.serialize()?;
+        assert_eq!(Account::decode(&bytes)?, account);
+        assert_eq!(account.indices(Uuid::nil()).len(), 1);
+
+        let record = database::view::ArchivedRecord::new(&bytes);
+        let view: AccountRef = record.view()?;
+        assert_eq!(view.email, "someone@example.com");
+        assert_eq!(view.logins, Some(3));
+        assert_eq!(record.project(Account::project_logins())?, Some(3));
+        assert_eq!(record.columns(&[Account::project_email().id, 1])?.len(), 2);
+
+        let mut account = account;
+        let mut patch = account.patch();
+        patch.set_logins(Some(4)).set_logins(Some(5));
+        assert_eq!(Account::changed(&patch), vec![2]);
+        assert_eq!(account.logins, Some(5));
+        Ok(())
+    }
+
+    #[test]
+    fn shorter_rows() {
+        use database::{record::RecordStruct, values_indices::DbValue};
+        let email = DbValue::String(String::from("someone@example.com"));
+        let account = Account::deserialize(vec![email.clone()]).expect("logins is optional");
+        assert_eq!(account.logins, None);
+        assert!(matches!(
+            Person::deserialize(vec![email]),
+            Err(error::ConvertError::MissingColumn)
+        ));
+    }
+
+    #[test]
+    fn table_names() {
+        use database::record::RecordStruct;
+        assert_eq!(Person::name(), "Person");
+        assert_eq!(Member::name(), "auth.people");
+        assert_eq!(Account::name(), "billing.Account");
+    }
+
+    #[test]
+    fn key_ranges() -> SResult<()> {
+        use database::key::{Key, KeyRange, Purpose, Tenant};
+        use database::record::RecordStruct;
+        let (tenant, id) = (Tenant::Named("testing"), Uuid::new_v4());
+        let row = Person::corpus_key(tenant.clone(), id).generate()?;
+        let shared = Tenant::named(String::from("testing"));
+        assert_eq!(row, Person::corpus_key(shared, id).generate()?);
+        let rows = Key::purpose_range(tenant.clone(), Person::name(), Purpose::ROW_TAG)?;
+        assert!(rows.begin <= row && row < rows.end);
+        let indices = Key::purpose_range(tenant.clone(), Person::name(), Purpose::INDEX_TAG)?;
+        assert!(!(indices.begin <= row && row < indices.end));
+        let everything = KeyRange::prefix(Key::tenant_prefix(tenant)?);
+        assert!(everything.begin <= row && row < everything.end);
+        let other = KeyRange::prefix(Key::tenant_prefix(Tenant::named("testing2"))?);
+        assert!(!(other.begin <= row && row < other.end));
+        Ok(())
+    }
+
+    #[test]
+    fn blob_keys() -> SResult<()> {
+        use database::key::{Key, Tenant};
+        let (tenant, id) = (Tenant::Named("testing"), Uuid::new_v4());
+        let bucket = String::from("avatars");
+        let range = Key::blob_range(tenant.clone(), &bucket, id)?;
+        for shard in [0, 1, u16::MAX] {
+            let key = Key::new_blob(tenant.clone(), bucket.as_str(), id, shard).generate()?;
+            assert!(range.begin <= key && key < range.end);
+        }
+        let other = Key::new_blob(tenant.clone(), "avatars2", id, 0).generate()?;
+        assert!(!(range.begin <= other && other < range.end));
+        assert!(Key::new_blob(tenant, "", id, 0).generate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tenant_names() -> SResult<()> {
+        use database::key::{Key, Tenant};
+        assert!(Key::tenant_prefix(Tenant::named("acme")).is_ok());
+        assert!(Key::tenant_prefix(Tenant::named("acme\0people")).is_err());
+        assert!(Key::tenant_prefix(Tenant::named("")).is_err());
+        assert!(Key::tenant_prefix(Tenant::Named("")).is_err());
+        assert!(Tenant::named("acme\0people").catalog_key().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tenant_prefixes() -> SResult<()> {
+        use database::key::{Key, KeyRange, Tenant};
+        let mut bytes = [0xab; 16];
+        bytes[..2].copy_from_slice(b"a\0");
+        let colliding = Key::tenant_prefix(Tenant::Id(Uuid::from_bytes(bytes)))?;
+        let named = KeyRange::prefix(Key::tenant_prefix(Tenant::Named("a"))?);
+        assert!(!(named.begin <= colliding && colliding < named.end));
+        let id = KeyRange::prefix(colliding);
+        let name = Key::tenant_prefix(Tenant::Named("a"))?;
+        assert!(!(id.begin <= name && name < id.end));
+        Ok(())
+    }
+
+    #[test]
+    fn catalog_keys() -> SResult<()> {
+        use database::key::{Key, Tenant};
+        let range = Key::catalog_range();
+        let id = Uuid::new_v4();
+        for tenant in [
+            Tenant::Named("testing"),
+            Tenant::named("testing"),
+            Tenant::Id(id),
+        ] {
+            let key = tenant.catalog_key()?;
+            assert!(range.begin <= key && key < range.end);
+            let parsed = Tenant::from_catalog_key(&key)?;
+            assert_eq!(parsed.as_bytes()?, tenant.as_bytes()?);
+        }
+        assert!(matches!(
+            Tenant::from_catalog_key(&Tenant::Id(id).catalog_key()?)?,
+            Tenant::Id(parsed) if parsed == id
+        ));
+        let row = Key::tenant_prefix(Tenant::Named("testing"))?;
+        assert!(!(range.begin <= row && row < range.end));
+        assert!(Tenant::Unset.catalog_key().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn byte_ranges() {
+        use database::blobstream::ByteRange;
+        assert_eq!(
+            ByteRange::parse("bytes=0-499"),
+            Some(ByteRange::Inclusive(0, 499))
+        );
+        assert_eq!(ByteRange::parse("bytes=500-"), Some(ByteRange::From(500)));
+        assert_eq!(ByteRange::parse("bytes=-500"), Some(ByteRange::Suffix(500)));
+        assert_eq!(ByteRange::parse("bytes=5-1"), None);
+        assert_eq!(ByteRange::parse("items=0-1"), None);
+        assert_eq!(ByteRange::Inclusive(0, 499).resolve(100), Some((0, 100)));
+        assert_eq!(ByteRange::Suffix(500).resolve(1000), Some((500, 500)));
+        assert_eq!(ByteRange::From(1000).resolve(1000), None);
+    }
+
+    #[test]
+    fn transaction_errors() {
+        use database::database::TransactionError;
+        use error::ExothermError;
+        let fdb = foundationdb::FdbError::from_code(1020);
+        let code = ExothermError::FoundationDB(fdb)
+            .fdb_error()
+            .map(|e| e.code());
+        assert_eq!(code, Some(1020));
+        let conflict = ExothermError::VersionConflict {
+            table: "Person",
+            pk: Uuid::nil(),
+            expected: 1,
+            found: 2,
+        };
+        assert!(conflict.fdb_error().is_none());
+    }
+
+    ///The network can only be booted once per process, so it is shared by all tests and never stopped
+    async fn testing_database() -> SResult<Database> {
+        static NETWORK: std::sync::Once = std::sync::Once::new();
+        NETWORK.call_once(|| std::mem::forget(unsafe { foundationdb::boot() }));
+        Database::new(database::key::Tenant::Named("testing"), false).await
+    }
+
+    fn person(name: &str) -> Person {
+        Person {
+            name: String::from(name),
+            password: String::from("TestTestTestTestTest"),
+        }
+    }
+
     #[tokio::test]
     async fn insert() -> SResult<()> {
         //let _guard = unsafe { foundationdb::boot() };
-        let db = Database::new(database::key::Tenant::Named("testing"), true).await?;
+        let db = testing_database().await?;
         let id = Uuid::new_v4();
         let person = Person {
             name: String::from("NameNameNameNameNamevName"),
@@ -38,7 +240,7 @@ mod tests {
                 .query_index(database::transaction::Query::Equal(eq), false)
                 .await?;
 
-            assert!(result.ids.len() > 0);
+            assert!(!result.ids.is_empty());
 
             println!("{result:#?}");
             Ok(())
@@ -67,4 +269,729 @@ mod tests {
         .await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn partial_updates() -> SResult<()> {
+        let db = testing_database().await?;
+        let id = Uuid::new_v4();
+        db.transact(|transaction| async move {
+            transaction.put_value(&person("Updated"), id).await?;
+            let person = transaction
+                .update::<Person, _>(id, |patch| {
+                    patch.set_password(String::from("noch ein passwort"));
+                })
+                .await?;
+            assert_eq!(person.password, "noch ein passwort");
+            assert_eq!(person.name, "Updated");
+            transaction.clear_value::<Person>(id).await
+        })
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_semantics() -> SResult<()> {
+        let db = testing_database().await?;
+        let id = Uuid::new_v4();
+        let person = person("Written");
+        db.transact(|transaction| {
+            let person = &person;
+            async move {
+                assert!(transaction.insert(person, id).await.is_ok());
+                assert!(transaction.insert(person, id).await.is_err());
+                assert!(transaction.replace(person, Uuid::new_v4()).await.is_err());
+                assert!(transaction.upsert(person, id).await?);
+                transaction.clear_value::<Person>(id).await
+            }
+        })
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn row_versions() -> SResult<()> {
+        let db = testing_database().await?;
+        let id = Uuid::new_v4();
+        db.transact(|transaction| async move {
+            transaction.put_value(&person("Versioned"), id).await?;
+            let Some(versioned) = transaction.get_versioned::<Person>(id).await? else {
+                panic!("row {id} is missing");
+            };
+            let stale = versioned.version - 1;
+            assert!(
+                transaction
+                    .put_value_if_version(&versioned.record, id, stale)
+                    .await
+                    .is_err()
+            );
+            transaction
+                .put_value_if_version(&versioned.record, id, versioned.version)
+                .await?;
+            transaction.clear_value::<Person>(id).await
+        })
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_only() -> SResult<()> {
+        let db = testing_database().await?;
+        let read_only = db.options().clone().read_only(true);
+        let written = db
+            .transact_with_options(&read_only, |transaction| async move {
+                transaction
+                    .upsert(&person("Read only"), Uuid::new_v4())
+                    .await
+            })
+            .await;
+        assert!(matches!(written, Err(error::ExothermError::ReadOnly)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn timeouts() -> SResult<()> {
+        let db = testing_database().await?;
+        let options = db
+            .options()
+            .clone()
+            .timeout(std::time::Duration::from_millis(1));
+        let read = db
+            .transact_with_options(&options, |transaction| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                transaction.get_value::<Person>(Uuid::new_v4()).await
+            })
+            .await;
+        //transaction_timed_out is not retried
+        assert!(matches!(read, Err(error::ExothermError::FoundationDB(e)) if e.code() == 1031));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_limits() -> SResult<()> {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        let db = testing_database().await?;
+        //The most tags a transaction may carry, they are kept across retries
+        let options = (0..5).fold(db.options().clone().retry_limit(2), |options, tag| {
+            options.tag(format!("retry{tag}"))
+        });
+        let attempts = AtomicU32::new(0);
+        let result = db
+            .transact_with_options(&options, |_transaction| {
+                let attempts = &attempts;
+                async move {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    let not_committed = foundationdb::FdbError::from_code(1020);
+                    Err::<(), _>(error::ExothermError::FoundationDB(not_committed))
+                }
+            })
+            .await;
+        assert!(result.is_err());
+        //The first attempt and two retries
+        assert_eq!(attempts.into_inner(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn idempotency() -> SResult<()> {
+        let db = testing_database().await?;
+        let request = Uuid::new_v4();
+        for attempt in 0..2u32 {
+            let outcome = db
+                .transact(|transaction| async move {
+                    transaction
+                        .idempotent(request, || async { Ok(attempt) })
+                        .await
+                })
+                .await?;
+            assert_eq!(outcome, 0);
+        }
+
+        //A commit that may have succeeded is retried, the body runs again if it did not
+        let (request, runs) = (Uuid::new_v4(), std::sync::atomic::AtomicU32::new(0));
+        let outcome = db
+            .transact(|transaction| {
+                let runs = &runs;
+                async move {
+                    let run = transaction
+                        .idempotent(request, || async {
+                            Ok(runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst))
+                        })
+                        .await?;
+                    if !transaction.maybe_commited {
+                        let unknown = foundationdb::FdbError::from_code(1021);
+                        return Err(error::ExothermError::FoundationDB(unknown));
+                    }
+                    Ok(run)
+                }
+            })
+            .await?;
+        assert_eq!((outcome, runs.into_inner()), (1, 2));
+        let stored = db
+            .transact(|transaction| async move {
+                transaction.idempotent(request, || async { Ok(7) }).await
+            })
+            .await?;
+        assert_eq!(stored, 1);
+
+        assert!(
+            db.expire_idempotency_keys(std::time::Duration::ZERO)
+                .await?
+                >= 2
+        );
+        let expired = db
+            .transact(|transaction| async move {
+                transaction.idempotent(request, || async { Ok(7) }).await
+            })
+            .await?;
+        assert_eq!(expired, 7);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn snapshot_reads() -> SResult<()> {
+        let db = testing_database().await?;
+        let (id, name) = (Uuid::new_v4(), format!("Snapshot {}", Uuid::new_v4()));
+        let name = &name;
+        db.transact(|transaction| async move {
+            transaction.put_value(&person(name), id).await?;
+            let eq = Person::name_index(Uuid::nil(), name);
+            let result = transaction
+                .query_index(database::transaction::Query::Equal(eq), false)
+                .await?;
+            assert_eq!(result.ids.len(), 1);
+            let eq = Person::name_index(Uuid::nil(), name);
+            let snapshot = transaction
+                .snapshot()
+                .query_index(database::transaction::Query::Equal(eq), false)
+                .await?;
+            assert_eq!(snapshot.ids, result.ids);
+            transaction.clear_value::<Person>(id).await
+        })
+        .await?;
+        Ok(())
+    }
+
+    ///Attempts of a transaction that another one writes the row `read` under, after its read version
+    async fn interfered_attempts(db: &Database, snapshot: bool) -> SResult<u32> {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        let (read, written) = (Uuid::new_v4(), Uuid::new_v4());
+        let attempts = AtomicU32::new(0);
+        db.transact(|transaction| {
+            let attempts = &attempts;
+            async move {
+                let first = attempts.fetch_add(1, Ordering::SeqCst) == 0;
+                if snapshot {
+                    transaction.snapshot().get_value::<Person>(read).await?;
+                } else {
+                    transaction.get_value::<Person>(written).await?;
+                    transaction.add_conflict_key(
+                        <Person as database::record::RecordStruct>::corpus_key(
+                            database::key::Tenant::Unset,
+                            read,
+                        ),
+                        foundationdb::options::ConflictRangeType::Read,
+                    )?;
+                }
+                if first {
+                    db.transact(|other| async move {
+                        other.put_value(&person("Interfering"), read).await
+                    })
+                    .await?;
+                }
+                transaction.put_value(&person("Interfered"), written).await
+            }
+        })
+        .await?;
+        db.transact(|transaction| async move {
+            transaction.clear_value::<Person>(read).await?;
+            transaction.clear_value::<Person>(written).await
+        })
+        .await?;
+        Ok(attempts.into_inner())
+    }
+
+    #[tokio::test]
+    async fn conflicts() -> SResult<()> {
+        let db = testing_database().await?;
+        //The conflict key makes the first attempt fail to commit
+        assert_eq!(interfered_attempts(&db, false).await?, 2);
+        //A snapshot read of the same row adds no conflict
+        assert_eq!(interfered_attempts(&db, true).await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bulk_writes() -> SResult<()> {
+        let db = testing_database().await?;
+        let run = Uuid::new_v4();
+        let imported = (0..10).map(|i| (Uuid::new_v4(), person(&format!("Imported {run} {i}"))));
+        let options = database::bulk::BulkOptions::new()
+            .batch_rows(3)
+            .parallelism(2);
+        let mut reported = 0;
+        let report = db.bulk_put(imported, options, |_| reported += 1).await?;
+        assert!(report.failed.is_empty());
+        assert_eq!(report.rows_written, 10);
+        assert_eq!(report.checkpoint.rows, 10);
+        assert_eq!(reported, 4);
+        for i in 0..10 {
+            let name = Person::name_index(Uuid::nil(), &format!("Imported {run} {i}"));
+            let query = database::transaction::Query::Equal(name);
+            assert_eq!(db.delete_where::<Person>(query).await?, 1);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_where() -> SResult<()> {
+        let db = testing_database().await?;
+        let name = format!("Deleted {}", Uuid::new_v4());
+        let name = &name;
+        db.transact(|transaction| async move {
+            for _ in 0..3 {
+                transaction.put_value(&person(name), Uuid::new_v4()).await?;
+            }
+            Ok(())
+        })
+        .await?;
+        let query = || database::transaction::Query::Equal(Person::name_index(Uuid::nil(), name));
+        assert_eq!(db.delete_where::<Person>(query()).await?, 3);
+        assert_eq!(db.delete_where::<Person>(query()).await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn truncate_tables() -> SResult<()> {
+        let db = testing_database().await?;
+        let members = db.transact(|transaction| async move {
+            let member = Member {
+                name: String::from("Member"),
+            };
+            transaction.put_value(&member, Uuid::new_v4()).await
+        });
+        members.await?;
+        assert!(db.truncate_table_dry_run::<Member>().await?.rows >= 1);
+        db.truncate_table::<Member>().await?;
+        assert_eq!(db.truncate_table_dry_run::<Member>().await?.total(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_log() -> SResult<()> {
+        let db = testing_database().await?;
+        let id = Uuid::new_v4();
+        let logged = db.options().clone().capture_changes(true);
+        let mut cursor = database::changes::ChangeCursor::start();
+        while let Some(last) = db.read_changes(cursor, 1_000).await?.last() {
+            cursor = last.cursor;
+        }
+        for _ in 0..2 {
+            db.transact_with_options(&logged, |transaction| async move {
+                transaction.upsert(&person("Logged"), id).await
+            })
+            .await?;
+        }
+        let changes = db.tail_changes(cursor).await?;
+        let changes: Vec<_> = changes.iter().filter(|c| c.pk == id).collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].op, database::changes::ChangeOp::Put);
+        assert!(changes[0].old.is_none());
+        assert!(changes[1].old.is_some());
+        db.transact(|transaction| async move { transaction.clear_value::<Person>(id).await })
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn outbox() -> SResult<()> {
+        let db = testing_database().await?;
+        let id = Uuid::new_v4();
+        db.transact(
+            |transaction| async move { transaction.enqueue("person_saved", id.as_bytes()) },
+        )
+        .await?;
+        let (sink, mut events) = database::outbox::ChannelSink::new();
+        let worker = database::outbox::OutboxWorker::new(&db, sink).batch(2);
+        while worker.run_once().await? > 0 {}
+        let mut delivered = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            delivered.push(event);
+        }
+        assert!(delivered.iter().any(|e| e.payload == id.as_bytes()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscriptions() -> SResult<()> {
+        let db = testing_database().await?;
+        let notify = db.options().clone().notify_subscribers(true);
+        let name = format!("Subscribed {}", Uuid::new_v4());
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let person = Person {
+            name: name.clone(),
+            password: String::from("TestTestTestTestTest"),
+        };
+        let woken = std::time::Duration::from_secs(10);
+        db.transact_with_options(&notify, |transaction| {
+            let person = &person;
+            async move { transaction.put_value(person, first).await }
+        })
+        .await?;
+        let query = database::transaction::Query::Equal(Person::name_index(Uuid::nil(), &name));
+        let mut subscription = db.subscribe::<Person>(query)?;
+        let diff = subscription.next().await?;
+        assert_eq!((diff.added, diff.removed), (vec![first], vec![]));
+
+        let write = async {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            db.transact_with_options(&notify, |transaction| {
+                let person = &person;
+                async move {
+                    transaction.put_value(person, second).await?;
+                    transaction.clear_value::<Person>(first).await
+                }
+            })
+            .await
+        };
+        let (diff, written) =
+            tokio::time::timeout(woken, async { tokio::join!(subscription.next(), write) })
+                .await
+                .expect("the write wakes the subscription");
+        written?;
+        let diff = diff?;
+        assert_eq!((diff.added, diff.removed), (vec![second], vec![first]));
+
+        let write = async {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            db.transact(|transaction| {
+                let person = &person;
+                async move { transaction.upsert(person, second).await }
+            })
+            .await
+        };
+        let (watched, written) = tokio::time::timeout(woken, async {
+            tokio::join!(db.watch_value::<Person>(second), write)
+        })
+        .await
+        .expect("the write wakes the watch");
+        watched?;
+        written?;
+        db.transact(|transaction| async move { transaction.clear_value::<Person>(second).await })
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn large_changes() -> SResult<()> {
+        use database::record::RecordStruct;
+        let db = testing_database().await?;
+        let id = Uuid::new_v4();
+        let logged = db.options().clone().capture_changes(true);
+        let mut cursor = database::changes::ChangeCursor::start();
+        while let Some(last) = db.read_changes(cursor, 1_000).await?.last() {
+            cursor = last.cursor;
+        }
+        //Both versions together are larger than a single FoundationDB value
+        for name in ["a", "b"] {
+            let person = Person {
+                name: name.repeat(60_000),
+                password: String::from("TestTestTestTestTest"),
+            };
+            db.transact_with_options(&logged, |transaction| {
+                let person = &person;
+                async move { transaction.upsert(person, id).await }
+            })
+            .await?;
+        }
+        let changes = db.read_changes(cursor, 1_000).await?;
+        let change = changes.iter().rfind(|c| c.pk == id).expect("logged");
+        let old = Person::decode(change.old.as_ref().expect("old row").bytes())?;
+        let new = Person::decode(change.new.as_ref().expect("new row").bytes())?;
+        assert_eq!((old.name.len(), &old.name[..1]), (60_000, "a"));
+        assert_eq!((new.name.len(), &new.name[..1]), (60_000, "b"));
+        assert_eq!(db.read_changes(change.cursor, 1_000).await?.len(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blobs() -> SResult<()> {
+        let db = testing_database().await?;
+        let id = Uuid::new_v4();
+
+        let blob: Vec<u8> = (0..120_000u32).map(|i| i as u8).collect();
+        db.transact(|transaction| {
+            let blob = &blob;
+            async move { transaction.put_blob("avatars", id, blob).await }
+        })
+        .await?;
+        db.transact(|transaction| {
+            let blob = &blob;
+            async move {
+                assert_eq!(
+                    transaction.get_blob("avatars", id).await?.as_ref(),
+                    Some(blob)
+                );
+                assert!(transaction.delete_blob("avatars", id).await?);
+                assert!(transaction.get_blob("avatars", id).await?.is_none());
+                Ok(())
+            }
+        })
+        .await?;
+
+        let attachment = b"the same attachment".to_vec();
+        db.transact(|transaction| {
+            let attachment = &attachment;
+            async move {
+                let first = transaction.put_content("attachments", attachment).await?;
+                let second = transaction.put_content("attachments", attachment).await?;
+                assert_eq!(first, second);
+                assert_eq!(transaction.content_refs("attachments", &first).await?, 2);
+                assert!(!transaction.release_content("attachments", &first).await?);
+                transaction
+                    .put_blob("attachments", first.id(), b"unrelated")
+                    .await?;
+                assert!(transaction.delete_blob("attachments", first.id()).await?);
+                assert_eq!(
+                    transaction
+                        .get_content("attachments", &first)
+                        .await?
+                        .as_ref(),
+                    Some(attachment)
+                );
+                assert!(transaction.release_content("attachments", &first).await?);
+                assert!(
+                    transaction
+                        .get_content("attachments", &first)
+                        .await?
+                        .is_none()
+                );
+                assert!(!transaction.release_content("attachments", &first).await?);
+                Ok(())
+            }
+        })
+        .await?;
+
+        let document = Document {
+            title: String::from("Report"),
+            body: database::blobstore::Blob::new("documents", b"first draft".to_vec()),
+        };
+        let address = document.body.address().clone();
+        db.transact(|transaction| {
+            let document = &document;
+            async move { transaction.put_value(document, id).await }
+        })
+        .await?;
+        db.transact(|transaction| {
+            let address = &address;
+            async move {
+                let stored = transaction.get_value::<Document>(id).await?.expect("row");
+                assert_eq!(stored.body.data(&transaction).await?, b"first draft");
+                let replaced = Document {
+                    body: database::blobstore::Blob::new("documents", b"second".to_vec()),
+                    ..stored
+                };
+                transaction.put_value(&replaced, id).await?;
+                let old = transaction.get_blob(&address.bucket, address.id).await?;
+                assert!(old.is_none());
+                assert!(transaction.clear_value::<Document>(id).await?);
+                let address = replaced.body.address();
+                let new = transaction.get_blob(&address.bucket, address.id).await?;
+                assert!(new.is_none());
+                Ok(())
+            }
+        })
+        .await?;
+        let copy = Uuid::new_v4();
+        db.transact(|transaction| {
+            let (document, address) = (&document, &address);
+            async move {
+                transaction.put_value(document, id).await?;
+                transaction.put_value(document, copy).await?;
+                assert!(transaction.clear_value::<Document>(id).await?);
+                let copied = transaction.get_value::<Document>(copy).await?.expect("row");
+                assert_eq!(copied.body.data(&transaction).await?, b"first draft");
+                assert!(transaction.clear_value::<Document>(copy).await?);
+                let gone = transaction.get_blob(&address.bucket, address.id).await?;
+                assert!(gone.is_none());
+                Ok(())
+            }
+        })
+        .await?;
+        db.transact(|transaction| {
+            let document = &document;
+            async move {
+                assert!(transaction.truncate_table::<Document>().is_err());
+                transaction.put_value(document, copy).await
+            }
+        })
+        .await?;
+        db.truncate_table::<Document>().await?;
+        let truncated = db
+            .transact(|transaction| {
+                let address = &address;
+                async move { transaction.get_blob(&address.bucket, address.id).await }
+            })
+            .await?;
+        assert!(truncated.is_none());
+        let (left, right) = (Uuid::new_v4(), Uuid::new_v4());
+        db.transact(|transaction| async move {
+            let shared = || Document {
+                title: String::from("Shared"),
+                body: database::blobstore::Blob::deduplicated("documents", b"shared".to_vec()),
+            };
+            transaction.put_value(&shared(), left).await?;
+            transaction.put_value(&shared(), right).await?;
+            let content = database::content::ContentId::of(b"shared");
+            assert_eq!(transaction.content_refs("documents", &content).await?, 2);
+            assert!(transaction.clear_value::<Document>(left).await?);
+            let stored = transaction
+                .get_value::<Document>(right)
+                .await?
+                .expect("row");
+            assert_eq!(stored.body.data(&transaction).await?, b"shared");
+            assert!(transaction.clear_value::<Document>(right).await?);
+            assert_eq!(transaction.content_refs("documents", &content).await?, 0);
+            assert!(
+                transaction
+                    .get_content("documents", &content)
+                    .await?
+                    .is_none()
+            );
+            Ok(())
+        })
+        .await?;
+
+        let large: Vec<u8> = (0..12_000_000u32).map(|i| (i % 251) as u8).collect();
+        let manifest = db.upload_blob("videos", id, &large).await?;
+        assert_eq!(manifest.size, large.len() as u64);
+        assert_eq!(db.download_blob("videos", id).await?, Some(large));
+        let abandoned = db.begin_blob_upload("videos", id).await?;
+        drop(abandoned);
+        let collected = db.collect_abandoned_uploads(std::time::Duration::ZERO);
+        assert!(collected.await? >= 1);
+        let mut running = db.begin_blob_upload("videos", id).await?;
+        running.write(b"still running").await?;
+        db.collect_abandoned_uploads(std::time::Duration::ZERO)
+            .await?;
+        assert!(matches!(
+            running.finish().await,
+            Err(error::ExothermError::UploadAbandoned { .. })
+        ));
+
+        {
+            use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+            let streamed: Vec<u8> = (0..300_000u32).map(|i| (i % 13) as u8).collect();
+            let mut writer = db.blob_writer("videos", id).await?;
+            for chunk in streamed.chunks(7_000) {
+                writer.write_all(chunk).await?;
+            }
+            writer.shutdown().await?;
+            assert_eq!(
+                writer.manifest().map(|m| m.size),
+                Some(streamed.len() as u64)
+            );
+
+            let mut reader = db.open_blob("videos", id).await?.expect("blob was written");
+            reader.seek(std::io::SeekFrom::Start(120_000)).await?;
+            let mut tail = Vec::new();
+            reader.read_to_end(&mut tail).await?;
+            assert_eq!(tail, streamed[120_000..]);
+
+            let range = database::blobstream::ByteRange::Inclusive(51_000, 52_999);
+            let mut reader = db.open_blob("videos", id).await?.expect("blob was written");
+            reader = reader.range(range)?;
+            let mut part = Vec::new();
+            reader.read_to_end(&mut part).await?;
+            assert_eq!(part, streamed[51_000..53_000]);
+            assert_eq!(reader.content_range(), "bytes 51000-52999/300000");
+
+            let options = database::blobstore::BlobOptions::new()
+                .content_type("application/octet-stream")
+                .zstd(3);
+            let mut writer = db.blob_writer_with_options("videos", id, &options).await?;
+            writer.write_all(&streamed).await?;
+            writer.shutdown().await?;
+            let mut reader = db.open_blob("videos", id).await?.expect("blob was written");
+            let manifest = reader.manifest().clone();
+            assert_eq!(manifest.compression, database::blobstore::Compression::Zstd);
+            assert_eq!(
+                manifest.content_type.as_deref(),
+                Some("application/octet-stream")
+            );
+            let mut all = Vec::new();
+            reader.read_to_end(&mut all).await?;
+            assert_eq!(all, streamed);
+            assert_eq!(db.download_blob("videos", id).await?, Some(streamed));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tenants() -> SResult<()> {
+        let db = testing_database().await?;
+        let id = Uuid::new_v4();
+        let person = Person {
+            name: String::from("NameNameNameNameNamevName"),
+            password: String::from("TestTestTestTestTest"),
+        };
+
+        let source = database::key::Tenant::named(format!("tenant-{}", Uuid::new_v4()));
+        let copy = database::key::Tenant::Id(Uuid::new_v4());
+        assert!(db.create_tenant(&source).await?);
+        assert!(!db.create_tenant(&source).await?);
+        db.transact_with_tenant(source.clone(), |transaction| {
+            let person = &person;
+            async move { transaction.put_value(person, id).await }
+        })
+        .await?;
+        let listed = db.list_tenants().await?;
+        assert!(
+            listed
+                .iter()
+                .any(|info| info.tenant.as_bytes().ok() == source.as_bytes().ok())
+        );
+        let stats = db.tenant_stats(&source).await?;
+        assert!(stats.estimated_blob_size <= stats.estimated_size);
+        let mut export = Vec::new();
+        let exported = db.export_tenant(&source, &mut export).await?;
+        assert!(exported > 0);
+        assert_eq!(
+            db.import_tenant(&copy, &mut export.as_slice()).await?,
+            exported
+        );
+        let copied = db
+            .transact_with_tenant(copy.clone(), |transaction| async move {
+                transaction.get_value::<Person>(id).await
+            })
+            .await?;
+        assert_eq!(copied.map(|p| p.name), Some(person.name.clone()));
+        assert!(db.delete_tenant(&source).await?);
+        assert!(db.delete_tenant(&copy).await?);
+        assert!(!db.delete_tenant(&copy).await?);
+        assert_eq!(db.drop_tenant_dry_run(copy).await?.total(), 0);
+
+        //An id starting with a name and a NUL byte must not share the keys of that name
+        let name = Uuid::new_v4().simple().to_string()[..8].to_string();
+        let mut bytes = [0xab; 16];
+        bytes[..8].copy_from_slice(name.as_bytes());
+        bytes[8] = 0;
+        let named = database::key::Tenant::named(name);
+        let colliding = database::key::Tenant::Id(Uuid::from_bytes(bytes));
+        db.transact_with_tenant(colliding.clone(), |transaction| {
+            let person = &person;
+            async move { transaction.put_value(person, id).await }
+        })
+        .await?;
+        let mut export = Vec::new();
+        assert_eq!(db.export_tenant(&named, &mut export).await?, 0);
+        db.delete_tenant(&named).await?;
+        let kept = db
+            .transact_with_tenant(colliding.clone(), |transaction| async move {
+                transaction.get_value::<Person>(id).await
+            })
+            .await?;
+        assert!(kept.is_some());
+        db.delete_tenant(&colliding).await?;
+        Ok(())
+    }
 }